@@ -0,0 +1,262 @@
+use crate::{
+    decode::Width,
+    error::SimError,
+    interrupt::InterruptHandler,
+    machine::Machine,
+    tables::{Registers, ZERO_FLAG},
+};
+use std::collections::VecDeque;
+
+// 80x25 text, a character and an attribute to each cell
+const TEXT_MODE: u8 = 0x03;
+const TEXT_SEGMENT: u16 = 0xb800;
+const TEXT_COLUMNS: u16 = 80;
+// 320x200, a byte to each pixel
+const GRAPHICS_MODE: u8 = 0x13;
+const GRAPHICS_SEGMENT: u16 = 0xa000;
+const GRAPHICS_WIDTH: u16 = 320;
+const GRAPHICS_HEIGHT: u16 = 200;
+// as text, which is 8x8 to a character
+const GRAPHICS_COLUMNS: u16 = 40;
+const ROWS: u16 = 25;
+
+// a space, light grey on black
+const BLANK: u16 = 0x0720;
+
+const BELL: u8 = 0x07;
+const BACKSPACE: u8 = 0x08;
+const LINE_FEED: u8 = 0x0a;
+const CARRIAGE_RETURN: u8 = 0x0d;
+
+/// Stands in for the BIOS int 10h video services: setting mode 03h or 13h,
+/// moving the cursor, teletype output and writing pixels, all straight into
+/// video memory at B800h for text and A000h for graphics. Drawing text in
+/// mode 13h isn't done: there's no font, so teletype output there only moves
+/// the cursor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Video {
+    mode: u8,
+    row: u16,
+    column: u16,
+}
+
+impl Default for Video {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Video {
+    /// In text mode, as the BIOS leaves things.
+    pub fn new() -> Self {
+        Self {
+            mode: TEXT_MODE,
+            row: 0,
+            column: 0,
+        }
+    }
+
+    pub fn install(self, machine: &mut Machine) {
+        machine.set_interrupt_handler(0x10, self);
+    }
+
+    fn columns(&self) -> u16 {
+        match self.mode {
+            GRAPHICS_MODE => GRAPHICS_COLUMNS,
+            _ => TEXT_COLUMNS,
+        }
+    }
+
+    // AH=00h: AL is the mode, with the top bit set to keep what's on screen
+    fn set_mode(&mut self, machine: &mut Machine, mode: u8) -> Result<(), SimError> {
+        let clear = mode & 0b1000_0000 == 0;
+        let mode = mode & 0b0111_1111;
+        let (segment, fill, words) = match mode {
+            TEXT_MODE => (TEXT_SEGMENT, BLANK, TEXT_COLUMNS * ROWS),
+            GRAPHICS_MODE => (GRAPHICS_SEGMENT, 0, GRAPHICS_WIDTH * GRAPHICS_HEIGHT / 2),
+            _ => {
+                return Err(SimError::UnsupportedInterrupt {
+                    vector: 0x10,
+                    function: 0x00,
+                });
+            }
+        };
+
+        self.mode = mode;
+        (self.row, self.column) = (0, 0);
+        if clear {
+            for word in 0..words {
+                machine.write_memory(segment, word * 2, Width::Word, fill)?;
+            }
+        }
+        Ok(())
+    }
+
+    // AH=0Eh: writes AL at the cursor and moves it on, following carriage
+    // returns, line feeds and backspaces, and scrolling off the bottom. A
+    // cursor parked off the screen writes nothing until it's back on.
+    fn teletype(&mut self, machine: &mut Machine, char: u8) -> Result<(), SimError> {
+        match char {
+            BELL => {}
+            BACKSPACE => self.column = self.column.saturating_sub(1),
+            LINE_FEED => self.row += 1,
+            CARRIAGE_RETURN => self.column = 0,
+            _ => {
+                if self.mode == TEXT_MODE && self.row < ROWS && self.column < TEXT_COLUMNS {
+                    let cell =
+                        (self.row as usize * TEXT_COLUMNS as usize + self.column as usize) * 2;
+                    machine.write_memory(TEXT_SEGMENT, cell as u16, Width::Byte, char as u16)?;
+                }
+                self.column += 1;
+                if self.column >= self.columns() {
+                    self.column = 0;
+                    self.row += 1;
+                }
+            }
+        }
+
+        if self.row >= ROWS {
+            self.row = ROWS - 1;
+            if self.mode == TEXT_MODE {
+                self.scroll(machine)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Moves every line of text up one, blanking the bottom one
+    fn scroll(&mut self, machine: &mut Machine) -> Result<(), SimError> {
+        for cell in TEXT_COLUMNS..TEXT_COLUMNS * ROWS {
+            let below = machine.read_memory(TEXT_SEGMENT, cell * 2, Width::Word);
+            machine.write_memory(TEXT_SEGMENT, (cell - TEXT_COLUMNS) * 2, Width::Word, below)?;
+        }
+        for cell in TEXT_COLUMNS * (ROWS - 1)..TEXT_COLUMNS * ROWS {
+            machine.write_memory(TEXT_SEGMENT, cell * 2, Width::Word, BLANK)?;
+        }
+        Ok(())
+    }
+}
+
+impl InterruptHandler for Video {
+    fn interrupt(&mut self, machine: &mut Machine, vector: u8) -> Result<(), SimError> {
+        let function = machine.cpu.get_value(Registers::_AH) as u8;
+        match function {
+            0x00 => {
+                let mode = machine.cpu.get_value(Registers::_AL) as u8;
+                self.set_mode(machine, mode)?;
+            }
+            // the page in BH is ignored, there's only the one. Anywhere off the
+            // screen hides the cursor.
+            0x02 => {
+                self.row = machine.cpu.get_value(Registers::_DH);
+                self.column = machine.cpu.get_value(Registers::_DL);
+            }
+            // AL is the colour, CX the column and DX the row
+            0x0c => {
+                let (x, y) = (
+                    machine.cpu.get_value(Registers::_CX),
+                    machine.cpu.get_value(Registers::_DX),
+                );
+                if self.mode == GRAPHICS_MODE && x < GRAPHICS_WIDTH && y < GRAPHICS_HEIGHT {
+                    let colour = machine.cpu.get_value(Registers::_AL);
+                    machine.write_memory(
+                        GRAPHICS_SEGMENT,
+                        y * GRAPHICS_WIDTH + x,
+                        Width::Byte,
+                        colour,
+                    )?;
+                }
+            }
+            0x0e => {
+                let char = machine.cpu.get_value(Registers::_AL) as u8;
+                self.teletype(machine, char)?;
+            }
+            _ => return Err(SimError::UnsupportedInterrupt { vector, function }),
+        }
+        Ok(())
+    }
+}
+
+/// Stands in for the BIOS int 16h keyboard services, reading keys from a
+/// queue filled in up front rather than from a real keyboard.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Keyboard {
+    // the scan code in the high byte, ascii in the low
+    keys: VecDeque<u16>,
+}
+
+impl Keyboard {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn install(self, machine: &mut Machine) {
+        machine.set_interrupt_handler(0x16, self);
+    }
+
+    pub fn push_key(&mut self, scan_code: u8, ascii: u8) {
+        self.keys.push_back(u16::from_le_bytes([ascii, scan_code]));
+    }
+
+    /// Queues up the keys that type out the text on a US keyboard. Newlines
+    /// come through as enter.
+    pub fn type_text(&mut self, text: &str) {
+        for ascii in text.bytes() {
+            let ascii = if ascii == b'\n' { b'\r' } else { ascii };
+            self.push_key(scan_code(ascii), ascii);
+        }
+    }
+}
+
+impl InterruptHandler for Keyboard {
+    fn interrupt(&mut self, machine: &mut Machine, vector: u8) -> Result<(), SimError> {
+        let function = machine.cpu.get_value(Registers::_AH) as u8;
+        match function {
+            // waits for a key, which there'd be no end to once they've run out
+            0x00 => {
+                let key = self.keys.pop_front().ok_or_else(|| SimError::Io {
+                    message: "waiting for a key after the last one".to_string(),
+                })?;
+                machine.cpu.set(Registers::_AX, key);
+            }
+            // the next key without taking it, and the zero flag set if there
+            // isn't one
+            0x01 => match self.keys.front() {
+                Some(key) => {
+                    machine.cpu.set(Registers::_AX, *key);
+                    machine.cpu.flags &= !ZERO_FLAG;
+                }
+                None => machine.cpu.flags |= ZERO_FLAG,
+            },
+            _ => return Err(SimError::UnsupportedInterrupt { vector, function }),
+        }
+        Ok(())
+    }
+}
+
+// The US keyboard scan code of the key that types an ascii character, or 0
+// for the ones that take more than a key
+fn scan_code(ascii: u8) -> u8 {
+    // each row of keys, and the scan code of its first
+    const ROWS: [(&[u8], u8); 4] = [
+        (b"1234567890-=", 0x02),
+        (b"qwertyuiop[]", 0x10),
+        (b"asdfghjkl;'`", 0x1e),
+        (b"\\zxcvbnm,./", 0x2b),
+    ];
+    match ascii.to_ascii_lowercase() {
+        0x1b => 0x01,
+        BACKSPACE => 0x0e,
+        b'\t' => 0x0f,
+        CARRIAGE_RETURN => 0x1c,
+        b' ' => 0x39,
+        key => ROWS
+            .iter()
+            .find_map(|(keys, first)| {
+                keys.iter()
+                    .position(|other| *other == key)
+                    .map(|index| first + index as u8)
+            })
+            .unwrap_or(0),
+    }
+}
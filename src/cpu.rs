@@ -0,0 +1,363 @@
+use crate::{
+    decode::{Opcode, Width},
+    tables::{
+        AUX_CARRY_FLAG, AX, BP, BX, CARRY_FLAG, CS, CX, DI, DS, DX, ES, FLAG_NAMES, OVERFLOW_FLAG,
+        PARITY_FLAG, Registers, SI, SIGN_FLAG, SP, SS, ZERO_FLAG,
+    },
+};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Cpu {
+    // indexed by the wide register encoding, ax through di
+    registers: [u16; 8],
+    // indexed by the segment register encoding, es through ds
+    segments: [u16; 4],
+    pub flags: u16,
+    pub ip: u16,
+}
+
+// Where a register lives: which wide register and which part of it, or which
+// segment register
+fn slot(register: Registers) -> Slot {
+    match register {
+        Registers::_AX => Slot::General(AX, Part::Wide),
+        Registers::_BX => Slot::General(BX, Part::Wide),
+        Registers::_CX => Slot::General(CX, Part::Wide),
+        Registers::_DX => Slot::General(DX, Part::Wide),
+        Registers::_SP => Slot::General(SP, Part::Wide),
+        Registers::_BP => Slot::General(BP, Part::Wide),
+        Registers::_SI => Slot::General(SI, Part::Wide),
+        Registers::_DI => Slot::General(DI, Part::Wide),
+        Registers::_AL => Slot::General(AX, Part::Low),
+        Registers::_AH => Slot::General(AX, Part::High),
+        Registers::_BL => Slot::General(BX, Part::Low),
+        Registers::_BH => Slot::General(BX, Part::High),
+        Registers::_CL => Slot::General(CX, Part::Low),
+        Registers::_CH => Slot::General(CX, Part::High),
+        Registers::_DL => Slot::General(DX, Part::Low),
+        Registers::_DH => Slot::General(DX, Part::High),
+        Registers::_ES => Slot::Segment(ES),
+        Registers::_CS => Slot::Segment(CS),
+        Registers::_SS => Slot::Segment(SS),
+        Registers::_DS => Slot::Segment(DS),
+    }
+}
+
+// The mask and sign bit of a value at the given width
+fn bounds(width: Width) -> (u16, u16) {
+    match width {
+        Width::Byte => (0x00ff, 0x0080),
+        Width::Word => (0xffff, 0x8000),
+    }
+}
+
+// A value at the given width, sign extended to 16 bits
+fn sign_extend(width: Width, value: u16) -> i16 {
+    match width {
+        Width::Byte => value as i8 as i16,
+        Width::Word => value as i16,
+    }
+}
+
+/// Gives the letters of the set flags, e.g. "CPZ".
+pub fn flag_names(flags: u16) -> String {
+    FLAG_NAMES
+        .iter()
+        .filter(|(flag, _)| flags & flag == *flag)
+        .map(|(_, name)| name)
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Part {
+    Wide,
+    Low,
+    High,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Slot {
+    General(u8, Part),
+    Segment(u8),
+}
+
+impl Cpu {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn flag(&self, flag: u16) -> bool {
+        self.flags & flag == flag
+    }
+
+    fn set_flag(&mut self, flag: u16, value: bool) {
+        if value {
+            self.flags |= flag;
+        } else {
+            self.flags &= !flag;
+        }
+    }
+
+    pub fn get_value(&self, register: Registers) -> u16 {
+        let (index, part) = match slot(register) {
+            Slot::General(index, part) => (index, part),
+            Slot::Segment(index) => return self.segments[index as usize],
+        };
+        let value = self.registers[index as usize];
+        match part {
+            Part::Wide => value,
+            Part::Low => value & 0xff,
+            Part::High => value >> 8,
+        }
+    }
+
+    // 8 bit registers take the low byte of the value
+    pub fn set(&mut self, register: Registers, value: u16) {
+        let (index, part) = match slot(register) {
+            Slot::General(index, part) => (index, part),
+            Slot::Segment(index) => {
+                self.segments[index as usize] = value;
+                return;
+            }
+        };
+        let current = &mut self.registers[index as usize];
+        match part {
+            Part::Wide => *current = value,
+            // little endian
+            Part::Low => *current = (*current & 0xff00) | (value & 0x00ff),
+            Part::High => *current = (*current & 0x00ff) | (value & 0x00ff) << 8,
+        }
+    }
+
+    // Sets the flags every arithmetic op derives from its result alone
+    fn set_result_flags(&mut self, width: Width, result: u16) {
+        let (mask, sign) = bounds(width);
+        self.set_flag(ZERO_FLAG, result & mask == 0);
+        self.set_flag(SIGN_FLAG, result & sign == sign);
+        // only ever the low byte, even for word results
+        self.set_flag(PARITY_FLAG, (result as u8).count_ones().is_multiple_of(2));
+    }
+
+    // Adds at the given width, setting all the arithmetic flags
+    pub fn add(&mut self, width: Width, destination: u16, source: u16) -> u16 {
+        self.add_with_carry(width, destination, source, false)
+    }
+
+    // add, plus one more if the carry flag is set
+    pub fn adc(&mut self, width: Width, destination: u16, source: u16) -> u16 {
+        let carry = self.flag(CARRY_FLAG);
+        self.add_with_carry(width, destination, source, carry)
+    }
+
+    // add by one, which leaves the carry alone
+    pub fn inc(&mut self, width: Width, value: u16) -> u16 {
+        let carry = self.flag(CARRY_FLAG);
+        let result = self.add(width, value, 1);
+        self.set_flag(CARRY_FLAG, carry);
+        result
+    }
+
+    fn add_with_carry(&mut self, width: Width, destination: u16, source: u16, carry: bool) -> u16 {
+        let (mask, sign) = bounds(width);
+        let sum = (destination & mask) as u32 + (source & mask) as u32 + carry as u32;
+        let result = sum as u16 & mask;
+
+        self.set_result_flags(width, result);
+        self.set_flag(CARRY_FLAG, sum > mask as u32);
+        self.set_flag(
+            AUX_CARRY_FLAG,
+            (destination ^ source ^ result) & 0x10 == 0x10,
+        );
+        // both operands had the same sign, and the result doesn't
+        self.set_flag(
+            OVERFLOW_FLAG,
+            (destination ^ result) & (source ^ result) & sign == sign,
+        );
+        result
+    }
+
+    // Sets the flags for the result of and/or/xor/test, which can't carry or
+    // overflow
+    pub fn logic(&mut self, width: Width, result: u16) -> u16 {
+        let (mask, _) = bounds(width);
+        let result = result & mask;
+
+        self.set_result_flags(width, result);
+        self.set_flag(CARRY_FLAG, false);
+        self.set_flag(OVERFLOW_FLAG, false);
+        result
+    }
+
+    // Shifts or rotates one bit at a time, the way the 8086 does it: the count
+    // isn't masked, and the carry and overflow are whatever the last step left
+    pub fn shift(&mut self, opcode: Opcode, width: Width, value: u16, count: u8) -> u16 {
+        let (mask, sign) = bounds(width);
+        let mut result = value & mask;
+
+        for _ in 0..count {
+            let carry = self.flag(CARRY_FLAG) as u16;
+            let (top, bottom) = ((result & sign == sign) as u16, result & 1);
+            let (shifted, carry) = match opcode {
+                Opcode::Rol => (((result << 1) | top) & mask, top),
+                Opcode::Ror => ((result >> 1) | (bottom * sign), bottom),
+                Opcode::Rcl => (((result << 1) | carry) & mask, top),
+                Opcode::Rcr => ((result >> 1) | (carry * sign), bottom),
+                Opcode::Shl => ((result << 1) & mask, top),
+                Opcode::Shr => (result >> 1, bottom),
+                _ => ((result >> 1) | (result & sign), bottom),
+            };
+            let overflow = match opcode {
+                // the top two bits of the result differ
+                Opcode::Ror | Opcode::Rcr => (shifted ^ (shifted << 1)) & sign == sign,
+                // the sign went out
+                Opcode::Shr => top == 1,
+                Opcode::Sar => false,
+                // the sign changed
+                _ => (shifted & sign == sign) != (carry == 1),
+            };
+            result = shifted;
+            self.set_flag(CARRY_FLAG, carry == 1);
+            self.set_flag(OVERFLOW_FLAG, overflow);
+        }
+        // rotates leave the rest alone, as does shifting by nothing
+        if count != 0 && matches!(opcode, Opcode::Shl | Opcode::Shr | Opcode::Sar) {
+            self.set_result_flags(width, result);
+        }
+        result
+    }
+
+    // Multiplies the accumulator by the source, into ax for bytes and dx:ax
+    // for words. Carry and overflow say whether the high half was needed.
+    pub fn mul(&mut self, width: Width, source: u16, signed: bool) {
+        let (mask, _) = bounds(width);
+        let bits = mask.count_ones();
+        let extend = |value: u16| {
+            if signed {
+                sign_extend(width, value) as i32 as u32
+            } else {
+                (value & mask) as u32
+            }
+        };
+        let product = extend(self.get_value(Registers::_AX)).wrapping_mul(extend(source));
+        let (low, high) = (product as u16 & mask, (product >> bits) as u16 & mask);
+
+        match width {
+            Width::Byte => self.set(Registers::_AX, (high << 8) | low),
+            Width::Word => {
+                self.set(Registers::_AX, low);
+                self.set(Registers::_DX, high);
+            }
+        }
+        // for imul the high half is only there for the sign
+        let needed = if signed {
+            product != extend(low)
+        } else {
+            high != 0
+        };
+        self.set_flag(CARRY_FLAG, needed);
+        self.set_flag(OVERFLOW_FLAG, needed);
+    }
+
+    // Divides ax for bytes and dx:ax for words by the source, leaving the
+    // quotient in the low half and the remainder in the high. Gives false,
+    // changing nothing, when the source is 0 or the quotient doesn't fit.
+    pub fn div(&mut self, width: Width, source: u16, signed: bool) -> bool {
+        let (mask, sign) = bounds(width);
+        let dividend = match width {
+            Width::Byte => self.get_value(Registers::_AX) as u32,
+            Width::Word => {
+                ((self.get_value(Registers::_DX) as u32) << 16)
+                    | self.get_value(Registers::_AX) as u32
+            }
+        };
+        if source & mask == 0 {
+            return false;
+        }
+
+        let (quotient, remainder) = if signed {
+            let dividend = match width {
+                Width::Byte => dividend as u16 as i16 as i64,
+                Width::Word => dividend as i32 as i64,
+            };
+            let divisor = sign_extend(width, source) as i64;
+            let quotient = dividend / divisor;
+            // the 8086 can't give back the most negative value either
+            if quotient.abs() >= sign as i64 {
+                return false;
+            }
+            (quotient as u16, (dividend % divisor) as u16)
+        } else {
+            let divisor = (source & mask) as u32;
+            let quotient = dividend / divisor;
+            if quotient > mask as u32 {
+                return false;
+            }
+            (quotient as u16, (dividend % divisor) as u16)
+        };
+
+        match width {
+            Width::Byte => self.set(
+                Registers::_AX,
+                ((remainder & mask) << 8) | (quotient & mask),
+            ),
+            Width::Word => {
+                self.set(Registers::_AX, quotient);
+                self.set(Registers::_DX, remainder);
+            }
+        }
+        true
+    }
+
+    // Subtracts at the given width, setting all the arithmetic flags. cmp is
+    // this without keeping the result.
+    pub fn sub(&mut self, width: Width, destination: u16, source: u16) -> u16 {
+        self.sub_with_borrow(width, destination, source, false)
+    }
+
+    // sub, taking one more away if the carry flag is set
+    pub fn sbb(&mut self, width: Width, destination: u16, source: u16) -> u16 {
+        let borrow = self.flag(CARRY_FLAG);
+        self.sub_with_borrow(width, destination, source, borrow)
+    }
+
+    // sub by one, which leaves the carry alone
+    pub fn dec(&mut self, width: Width, value: u16) -> u16 {
+        let carry = self.flag(CARRY_FLAG);
+        let result = self.sub(width, value, 1);
+        self.set_flag(CARRY_FLAG, carry);
+        result
+    }
+
+    // Takes the value away from 0
+    pub fn neg(&mut self, width: Width, value: u16) -> u16 {
+        self.sub(width, 0, value)
+    }
+
+    fn sub_with_borrow(
+        &mut self,
+        width: Width,
+        destination: u16,
+        source: u16,
+        borrow: bool,
+    ) -> u16 {
+        let (mask, sign) = bounds(width);
+        let (destination, source) = (destination & mask, source & mask);
+        let result = destination.wrapping_sub(source).wrapping_sub(borrow as u16) & mask;
+
+        self.set_result_flags(width, result);
+        self.set_flag(
+            CARRY_FLAG,
+            source as u32 + borrow as u32 > destination as u32,
+        );
+        self.set_flag(
+            AUX_CARRY_FLAG,
+            (destination ^ source ^ result) & 0x10 == 0x10,
+        );
+        // the operands had different signs, and the result took the source's
+        self.set_flag(
+            OVERFLOW_FLAG,
+            (destination ^ source) & (destination ^ result) & sign == sign,
+        );
+        result
+    }
+}
@@ -0,0 +1,1109 @@
+use crate::tables::{REGISTER_TABLE, Registers, SEGMENT_REGISTER_TABLE, WIDE_REGISTER_TABLE};
+use std::fmt::Display;
+
+// prefixes
+const LOCK: u8 = 0b1111_0000;
+const REPNE: u8 = 0b1111_0010;
+const REP: u8 = 0b1111_0011;
+// 001s s110, the segment register in bits 3-4
+const SEGMENT_OVERRIDE: u8 = 0b0010_0110;
+const SEGMENT_OVERRIDE_MASK: u8 = 0b1110_0111;
+
+// d
+const REG_IS_DEST: u8 = 0b0000_0010;
+
+// w
+const WIDE: u8 = 0b0000_0001;
+
+// s
+const SIGN_EXTEND: u8 = 0b0000_0010;
+
+// mod
+const MEM_MODE: u8 = 0b0000_0000;
+const MEM_MODE_BYTE_DIS: u8 = 0b0100_0000;
+const MEM_MODE_WORD_DIS: u8 = 0b1000_0000;
+const REG_MODE: u8 = 0b1100_0000;
+
+// r/m value that means a direct address when mod is 00
+const DIRECT_ADDRESS: u8 = 0b0000_0110;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Opcode {
+    Mov,
+    Add,
+    Sub,
+    Cmp,
+    Jo,
+    Jno,
+    Jb,
+    Jnb,
+    Je,
+    Jne,
+    Jbe,
+    Jnbe,
+    Js,
+    Jns,
+    Jp,
+    Jnp,
+    Jl,
+    Jnl,
+    Jle,
+    Jnle,
+    Loopnz,
+    Loopz,
+    Loop,
+    Jcxz,
+    Push,
+    Pop,
+    Pushf,
+    Popf,
+    Call,
+    // to a segment:offset, either given outright or from a pair in memory
+    CallFar,
+    Jmp,
+    JmpFar,
+    Ret,
+    Retf,
+    And,
+    Or,
+    Xor,
+    Test,
+    Not,
+    Rol,
+    Ror,
+    Rcl,
+    Rcr,
+    Shl,
+    Shr,
+    Sar,
+    Mul,
+    Imul,
+    Div,
+    Idiv,
+    Adc,
+    Sbb,
+    Inc,
+    Dec,
+    Neg,
+    Movs,
+    Cmps,
+    Scas,
+    Lods,
+    Stos,
+    Int,
+    Int3,
+    Into,
+    Iret,
+    Hlt,
+    Cli,
+    Sti,
+    Cld,
+    Std,
+}
+
+impl Opcode {
+    fn is_shift(self) -> bool {
+        matches!(
+            self,
+            Self::Rol | Self::Ror | Self::Rcl | Self::Rcr | Self::Shl | Self::Shr | Self::Sar
+        )
+    }
+
+    fn is_string(self) -> bool {
+        matches!(
+            self,
+            Self::Movs | Self::Cmps | Self::Scas | Self::Lods | Self::Stos
+        )
+    }
+
+    // The ones that stop repeating on the zero flag
+    fn compares(self) -> bool {
+        matches!(self, Self::Cmps | Self::Scas)
+    }
+
+    // The ones with a form taking a byte sign extended to a word
+    fn is_arithmetic(self) -> bool {
+        matches!(
+            self,
+            Self::Add
+                | Self::Or
+                | Self::Adc
+                | Self::Sbb
+                | Self::And
+                | Self::Sub
+                | Self::Xor
+                | Self::Cmp
+        )
+    }
+}
+
+impl Display for Opcode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mnemonic = match self {
+            Self::Mov => "mov",
+            Self::Add => "add",
+            Self::Sub => "sub",
+            Self::Cmp => "cmp",
+            Self::Jo => "jo",
+            Self::Jno => "jno",
+            Self::Jb => "jb",
+            Self::Jnb => "jnb",
+            Self::Je => "je",
+            Self::Jne => "jne",
+            Self::Jbe => "jbe",
+            Self::Jnbe => "jnbe",
+            Self::Js => "js",
+            Self::Jns => "jns",
+            Self::Jp => "jp",
+            Self::Jnp => "jnp",
+            Self::Jl => "jl",
+            Self::Jnl => "jnl",
+            Self::Jle => "jle",
+            Self::Jnle => "jnle",
+            Self::Loopnz => "loopnz",
+            Self::Loopz => "loopz",
+            Self::Loop => "loop",
+            Self::Jcxz => "jcxz",
+            Self::Push => "push",
+            Self::Pop => "pop",
+            Self::Pushf => "pushf",
+            Self::Popf => "popf",
+            Self::Call => "call",
+            Self::CallFar => "call far",
+            Self::Jmp => "jmp",
+            Self::JmpFar => "jmp far",
+            Self::Ret => "ret",
+            Self::Retf => "retf",
+            Self::And => "and",
+            Self::Or => "or",
+            Self::Xor => "xor",
+            Self::Test => "test",
+            Self::Not => "not",
+            Self::Rol => "rol",
+            Self::Ror => "ror",
+            Self::Rcl => "rcl",
+            Self::Rcr => "rcr",
+            Self::Shl => "shl",
+            Self::Shr => "shr",
+            Self::Sar => "sar",
+            Self::Mul => "mul",
+            Self::Imul => "imul",
+            Self::Div => "div",
+            Self::Idiv => "idiv",
+            Self::Adc => "adc",
+            Self::Sbb => "sbb",
+            Self::Inc => "inc",
+            Self::Dec => "dec",
+            Self::Neg => "neg",
+            Self::Movs => "movs",
+            Self::Cmps => "cmps",
+            Self::Scas => "scas",
+            Self::Lods => "lods",
+            Self::Stos => "stos",
+            Self::Int => "int",
+            Self::Int3 => "int3",
+            Self::Into => "into",
+            Self::Iret => "iret",
+            Self::Hlt => "hlt",
+            Self::Cli => "cli",
+            Self::Sti => "sti",
+            Self::Cld => "cld",
+            Self::Std => "std",
+        };
+        write!(f, "{mnemonic}")
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Width {
+    Byte,
+    Word,
+}
+
+impl Display for Width {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Byte => write!(f, "byte"),
+            Self::Word => write!(f, "word"),
+        }
+    }
+}
+
+// The registers (if any) an effective address is computed from, indexed by
+// the r/m field. `Direct` is the mod 00, r/m 110 special case.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Base {
+    BxSi,
+    BxDi,
+    BpSi,
+    BpDi,
+    Si,
+    Di,
+    Bp,
+    Bx,
+    Direct,
+}
+
+impl From<u8> for Base {
+    fn from(rm: u8) -> Self {
+        match rm & 0b0000_0111 {
+            0b0000_0000 => Self::BxSi,
+            0b0000_0001 => Self::BxDi,
+            0b0000_0010 => Self::BpSi,
+            0b0000_0011 => Self::BpDi,
+            0b0000_0100 => Self::Si,
+            0b0000_0101 => Self::Di,
+            0b0000_0110 => Self::Bp,
+            _ => Self::Bx,
+        }
+    }
+}
+
+impl Display for Base {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::BxSi => write!(f, "bx + si"),
+            Self::BxDi => write!(f, "bx + di"),
+            Self::BpSi => write!(f, "bp + si"),
+            Self::BpDi => write!(f, "bp + di"),
+            Self::Si => write!(f, "si"),
+            Self::Di => write!(f, "di"),
+            Self::Bp => write!(f, "bp"),
+            Self::Bx => write!(f, "bx"),
+            Self::Direct => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EffectiveAddress {
+    pub base: Base,
+    // for a direct address this is the address itself
+    pub displacement: i16,
+    // how the displacement was encoded, if it was at all
+    pub displacement_width: Option<Width>,
+}
+
+impl EffectiveAddress {
+    /// The segment the address is in unless a prefix says otherwise: ss when
+    /// it's based on bp, ds for everything else.
+    pub fn default_segment(&self) -> Registers {
+        match self.base {
+            Base::BpSi | Base::BpDi | Base::Bp => Registers::_SS,
+            _ => Registers::_DS,
+        }
+    }
+
+    fn format(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+        nasm: bool,
+        segment: Option<Registers>,
+    ) -> std::fmt::Result {
+        let segment = |f: &mut std::fmt::Formatter<'_>| match segment {
+            Some(segment) => write!(f, "{segment}:"),
+            None => Ok(()),
+        };
+        if self.base == Base::Direct {
+            write!(f, "[")?;
+            segment(f)?;
+            return write!(f, "{}]", self.displacement as u16);
+        }
+        // NASM picks the shortest displacement that holds the value, [bp]
+        // being the one that can't go without, so say so when that's not
+        // what was encoded
+        let explicit = nasm
+            && match self.displacement_width {
+                Some(Width::Byte) => self.displacement == 0 && self.base != Base::Bp,
+                Some(Width::Word) => self.displacement == self.displacement as i8 as i16,
+                None => false,
+            };
+        write!(f, "[")?;
+        if explicit && let Some(width) = self.displacement_width {
+            write!(f, "{width} ")?;
+        }
+        segment(f)?;
+        write!(f, "{}", self.base)?;
+        if self.displacement.is_negative() {
+            write!(f, " - {}", self.displacement.unsigned_abs())?;
+        } else if self.displacement != 0 || explicit {
+            write!(f, " + {}", self.displacement)?;
+        }
+        write!(f, "]")
+    }
+}
+
+impl Display for EffectiveAddress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.format(f, false, None)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    None,
+    Register(Registers),
+    Memory(EffectiveAddress),
+    // already sign extended to 16 bits where the encoding calls for it
+    Immediate(u16),
+    // displacement from the end of the instruction
+    Relative(i16),
+    Far { segment: u16, offset: u16 },
+}
+
+// Repeats a string instruction cx times. cmps and scas also stop once the
+// zero flag doesn't match: rep carries on while it's set, repne while it's not.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Repeat {
+    Rep,
+    Repne,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Prefixes {
+    pub lock: bool,
+    pub repeat: Option<Repeat>,
+    // a segment override, in place of the memory operand's default segment
+    pub segment: Option<Registers>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Instruction {
+    pub opcode: Opcode,
+    // destination first, like the assembly
+    pub operands: [Operand; 2],
+    pub width: Width,
+    // in bytes, including any prefixes, of which the 8086 allows any number
+    pub length: usize,
+    pub prefixes: Prefixes,
+    // how an immediate operand was encoded, a byte for one sign extended to a
+    // word
+    pub immediate_width: Option<Width>,
+    // the bytes after the prefixes, kept for the forms NASM never picks
+    // because there's a shorter one that does the same
+    long_form: Option<Encoding>,
+}
+
+// Up to the six bytes of an instruction after its prefixes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Encoding {
+    bytes: [u8; 6],
+    length: usize,
+}
+
+impl Instruction {
+    /// Formats the instruction so that NASM assembles it back to exactly the
+    /// bytes it was decoded from.
+    pub fn nasm(&self) -> Nasm<'_> {
+        Nasm(self)
+    }
+
+    fn fmt_operand(
+        &self,
+        operand: &Operand,
+        f: &mut std::fmt::Formatter<'_>,
+        nasm: bool,
+    ) -> std::fmt::Result {
+        match operand {
+            Operand::None => Ok(()),
+            Operand::Register(register) => write!(f, "{register}"),
+            Operand::Memory(address) => address.format(f, nasm, self.prefixes.segment),
+            // a count of bytes or an interrupt number, never negative
+            Operand::Immediate(value)
+                if matches!(self.opcode, Opcode::Ret | Opcode::Retf | Opcode::Int) =>
+            {
+                write!(f, "{value}")
+            }
+            Operand::Immediate(value) => {
+                // NASM sign extends a word that fits in a byte wherever it
+                // can, so say when that's not how it was encoded
+                if nasm
+                    && self.opcode.is_arithmetic()
+                    && self.immediate_width == Some(Width::Word)
+                    && *value as i16 == *value as i8 as i16
+                {
+                    write!(f, "strict word ")?;
+                }
+                match self.width {
+                    Width::Byte => write!(f, "{}", *value as u8 as i8),
+                    Width::Word => write!(f, "{}", *value as i16),
+                }
+            }
+            // relative to the start of the instruction, which is where NASM's
+            // $ points
+            Operand::Relative(displacement) if nasm => {
+                write!(f, "${:+}", *displacement as i32 + self.length as i32)
+            }
+            Operand::Relative(displacement) => write!(f, "{displacement}"),
+            Operand::Far { segment, offset } => write!(f, "{segment}:{offset}"),
+        }
+    }
+
+    // As the bytes themselves, prefixes first in the order they're otherwise
+    // written
+    fn format_bytes(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+        encoding: Encoding,
+    ) -> std::fmt::Result {
+        let mut bytes = Vec::new();
+        if self.prefixes.lock {
+            bytes.push(LOCK);
+        }
+        match self.prefixes.repeat {
+            Some(Repeat::Rep) => bytes.push(REP),
+            Some(Repeat::Repne) => bytes.push(REPNE),
+            None => {}
+        }
+        if let Some(segment) = self.prefixes.segment
+            && let Some((sr, _)) = SEGMENT_REGISTER_TABLE
+                .iter()
+                .find(|(_, register)| **register == segment)
+        {
+            bytes.push(SEGMENT_OVERRIDE | sr << 3);
+        }
+        bytes.extend(&encoding.bytes[..encoding.length]);
+
+        let bytes: Vec<_> = bytes.iter().map(|byte| format!("{byte:#04x}")).collect();
+        write!(f, "db {}", bytes.join(", "))
+    }
+
+    fn format(&self, f: &mut std::fmt::Formatter<'_>, nasm: bool) -> std::fmt::Result {
+        if nasm && let Some(encoding) = self.long_form {
+            return self.format_bytes(f, encoding);
+        }
+        if self.prefixes.lock {
+            write!(f, "lock ")?;
+        }
+        match self.prefixes.repeat {
+            Some(Repeat::Rep) if self.opcode.compares() => write!(f, "repe ")?,
+            Some(Repeat::Rep) => write!(f, "rep ")?,
+            Some(Repeat::Repne) => write!(f, "repne ")?,
+            None => {}
+        }
+        let [destination, source] = &self.operands;
+        // an override on a memory operand is written inside its brackets
+        if let Some(segment) = self.prefixes.segment
+            && !self
+                .operands
+                .iter()
+                .any(|operand| matches!(operand, Operand::Memory(_)))
+        {
+            write!(f, "{segment} ")?;
+        }
+        // the segment:offset is enough to say a direct one's far
+        match (self.opcode, destination) {
+            (Opcode::CallFar, Operand::Far { .. }) => write!(f, "{}", Opcode::Call)?,
+            (Opcode::JmpFar, Operand::Far { .. }) => write!(f, "{}", Opcode::Jmp)?,
+            (opcode, _) => write!(f, "{opcode}")?,
+        }
+        if self.opcode.is_string() {
+            match self.width {
+                Width::Byte => write!(f, "b")?,
+                Width::Word => write!(f, "w")?,
+            }
+        }
+
+        if *destination == Operand::None {
+            return Ok(());
+        }
+        write!(f, " ")?;
+        // NASM makes any jmp that can be short, short
+        if nasm
+            && self.opcode == Opcode::Jmp
+            && self.width == Width::Word
+            && let Operand::Relative(displacement) = destination
+            && i8::try_from(*displacement as i32 + 1).is_ok()
+        {
+            write!(f, "near ")?;
+        }
+
+        // nothing else says how wide the memory access is, a shift's cl
+        // included
+        let ambiguous = matches!(destination, Operand::Memory(_))
+            && (matches!(source, Operand::Immediate(_) | Operand::None) || self.opcode.is_shift())
+            && !matches!(self.opcode, Opcode::CallFar | Opcode::JmpFar);
+        if ambiguous && self.opcode != Opcode::Mov {
+            write!(f, "{} ", self.width)?;
+        }
+        self.fmt_operand(destination, f, nasm)?;
+
+        if *source != Operand::None {
+            write!(f, ", ")?;
+            if ambiguous && self.opcode == Opcode::Mov {
+                write!(f, "{} ", self.width)?;
+            }
+            self.fmt_operand(source, f, nasm)?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for Instruction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.format(f, false)
+    }
+}
+
+pub struct Nasm<'a>(&'a Instruction);
+
+impl Display for Nasm<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.format(f, true)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    TruncatedInstruction { offset: usize },
+    UnknownOpcode { byte: u8, offset: usize },
+}
+
+impl Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::TruncatedInstruction { offset } => {
+                write!(f, "truncated instruction at offset {offset:#x}")
+            }
+            Self::UnknownOpcode { byte, offset } => {
+                write!(f, "unknown opcode {byte:#04x} at offset {offset:#x}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+// Walks the bytes of a single instruction, refusing to read past the end of
+// the buffer.
+struct Reader<'a> {
+    buffer: &'a [u8],
+    offset: usize,
+    position: usize,
+    // how wide the immediate was, once there's been one
+    immediate: Option<Width>,
+}
+
+impl Reader<'_> {
+    fn byte(&mut self) -> Result<u8, DecodeError> {
+        let byte = *self
+            .buffer
+            .get(self.position)
+            .ok_or(DecodeError::TruncatedInstruction {
+                offset: self.offset,
+            })?;
+        self.position += 1;
+        Ok(byte)
+    }
+
+    fn word(&mut self) -> Result<u16, DecodeError> {
+        Ok(u16::from_le_bytes([self.byte()?, self.byte()?]))
+    }
+
+    fn immediate(&mut self, width: Width) -> Result<u16, DecodeError> {
+        self.immediate = Some(width);
+        match width {
+            Width::Byte => Ok(self.byte()? as u16),
+            Width::Word => self.word(),
+        }
+    }
+
+    fn sign_extended_byte(&mut self) -> Result<u16, DecodeError> {
+        self.immediate = Some(Width::Byte);
+        Ok(self.byte()? as i8 as i16 as u16)
+    }
+
+    fn unknown(&self, byte: u8) -> DecodeError {
+        DecodeError::UnknownOpcode {
+            byte,
+            offset: self.offset,
+        }
+    }
+}
+
+fn width(op: u8) -> Width {
+    if op & WIDE == WIDE {
+        Width::Word
+    } else {
+        Width::Byte
+    }
+}
+
+fn register(reg: u8, width: Width) -> Operand {
+    let table = match width {
+        Width::Byte => &REGISTER_TABLE,
+        Width::Word => &WIDE_REGISTER_TABLE,
+    };
+    Operand::Register(*table.get(&(reg & 0b0000_0111)).unwrap())
+}
+
+fn segment_register(sr: u8) -> Registers {
+    *SEGMENT_REGISTER_TABLE.get(&(sr & 0b0000_0011)).unwrap()
+}
+
+// Decodes the r/m half of a mod reg r/m byte, pulling in any displacement
+fn reg_or_mem(reader: &mut Reader, mod_reg_rm: u8, width: Width) -> Result<Operand, DecodeError> {
+    let rm = mod_reg_rm & 0b0000_0111;
+    let (displacement, displacement_width) = match mod_reg_rm & REG_MODE {
+        REG_MODE => return Ok(register(rm, width)),
+        MEM_MODE if rm == DIRECT_ADDRESS => {
+            return Ok(Operand::Memory(EffectiveAddress {
+                base: Base::Direct,
+                displacement: reader.word()? as i16,
+                displacement_width: Some(Width::Word),
+            }));
+        }
+        MEM_MODE => (0, None),
+        MEM_MODE_BYTE_DIS => (reader.byte()? as i8 as i16, Some(Width::Byte)),
+        MEM_MODE_WORD_DIS => (reader.word()? as i16, Some(Width::Word)),
+        _ => unreachable!(),
+    };
+    Ok(Operand::Memory(EffectiveAddress {
+        base: Base::from(rm),
+        displacement,
+        displacement_width,
+    }))
+}
+
+// The add/or/adc/sbb/and/sub/xor/cmp family shares one encoding, selected
+// either by bits 3-5 of the op or by the reg field of the immediate group.
+fn arithmetic(index: u8) -> Option<Opcode> {
+    match index & 0b0000_0111 {
+        0b000 => Some(Opcode::Add),
+        0b001 => Some(Opcode::Or),
+        0b010 => Some(Opcode::Adc),
+        0b011 => Some(Opcode::Sbb),
+        0b100 => Some(Opcode::And),
+        0b101 => Some(Opcode::Sub),
+        0b110 => Some(Opcode::Xor),
+        0b111 => Some(Opcode::Cmp),
+        _ => None,
+    }
+}
+
+fn conditional_jump(op: u8) -> Opcode {
+    match op & 0b0000_1111 {
+        0x0 => Opcode::Jo,
+        0x1 => Opcode::Jno,
+        0x2 => Opcode::Jb,
+        0x3 => Opcode::Jnb,
+        0x4 => Opcode::Je,
+        0x5 => Opcode::Jne,
+        0x6 => Opcode::Jbe,
+        0x7 => Opcode::Jnbe,
+        0x8 => Opcode::Js,
+        0x9 => Opcode::Jns,
+        0xa => Opcode::Jp,
+        0xb => Opcode::Jnp,
+        0xc => Opcode::Jl,
+        0xd => Opcode::Jnl,
+        0xe => Opcode::Jle,
+        _ => Opcode::Jnle,
+    }
+}
+
+/// Decodes the instruction starting at `offset` in `buffer` without executing
+/// it.
+pub fn decode(buffer: &[u8], offset: usize) -> Result<Instruction, DecodeError> {
+    let mut reader = Reader {
+        buffer,
+        offset,
+        position: offset,
+        immediate: None,
+    };
+    let mut prefixes = Prefixes::default();
+
+    let mut op = reader.byte()?;
+    loop {
+        match op {
+            LOCK => prefixes.lock = true,
+            REP => prefixes.repeat = Some(Repeat::Rep),
+            REPNE => prefixes.repeat = Some(Repeat::Repne),
+            _ if op & SEGMENT_OVERRIDE_MASK == SEGMENT_OVERRIDE => {
+                prefixes.segment = Some(segment_register(op >> 3));
+            }
+            _ => break,
+        }
+        op = reader.byte()?;
+    }
+    let start = reader.position - 1;
+
+    let (opcode, operands, width) = match op {
+        // reg/mem with register to either
+        0x00..=0x3f if op & 0b0000_0100 == 0 => {
+            let opcode = arithmetic(op >> 3).ok_or(reader.unknown(op))?;
+            let width = width(op);
+            let mod_reg_rm = reader.byte()?;
+            let reg = register(mod_reg_rm >> 3, width);
+            let rm = reg_or_mem(&mut reader, mod_reg_rm, width)?;
+            if op & REG_IS_DEST == REG_IS_DEST {
+                (opcode, [reg, rm], width)
+            } else {
+                (opcode, [rm, reg], width)
+            }
+        }
+        // segment register to/from the stack
+        0x06 | 0x0e | 0x16 | 0x1e => (
+            Opcode::Push,
+            [Operand::Register(segment_register(op >> 3)), Operand::None],
+            Width::Word,
+        ),
+        0x07 | 0x17 | 0x1f => (
+            Opcode::Pop,
+            [Operand::Register(segment_register(op >> 3)), Operand::None],
+            Width::Word,
+        ),
+        // immediate to accumulator
+        0x00..=0x3f if op & 0b0000_0110 == 0b0000_0100 => {
+            let opcode = arithmetic(op >> 3).ok_or(reader.unknown(op))?;
+            let width = width(op);
+            let value = reader.immediate(width)?;
+            (
+                opcode,
+                [register(0, width), Operand::Immediate(value)],
+                width,
+            )
+        }
+        0x40..=0x4f => {
+            let opcode = if op & 0b0000_1000 == 0 {
+                Opcode::Inc
+            } else {
+                Opcode::Dec
+            };
+            (
+                opcode,
+                [register(op, Width::Word), Operand::None],
+                Width::Word,
+            )
+        }
+        // register to/from the stack
+        0x50..=0x5f => {
+            let opcode = if op & 0b0000_1000 == 0 {
+                Opcode::Push
+            } else {
+                Opcode::Pop
+            };
+            (
+                opcode,
+                [register(op, Width::Word), Operand::None],
+                Width::Word,
+            )
+        }
+        0x70..=0x7f => {
+            let displacement = reader.byte()? as i8 as i16;
+            (
+                conditional_jump(op),
+                [Operand::Relative(displacement), Operand::None],
+                Width::Byte,
+            )
+        }
+        // immediate to reg/mem
+        0x80..=0x83 => {
+            let width = width(op);
+            let mod_reg_rm = reader.byte()?;
+            let opcode = arithmetic(mod_reg_rm >> 3).ok_or(reader.unknown(op))?;
+            let destination = reg_or_mem(&mut reader, mod_reg_rm, width)?;
+            let value = if op & (SIGN_EXTEND | WIDE) == SIGN_EXTEND | WIDE {
+                reader.sign_extended_byte()?
+            } else {
+                reader.immediate(width)?
+            };
+            (opcode, [destination, Operand::Immediate(value)], width)
+        }
+        // reg/mem and register, there's no direction to it
+        0x84 | 0x85 => {
+            let width = width(op);
+            let mod_reg_rm = reader.byte()?;
+            let reg = register(mod_reg_rm >> 3, width);
+            let rm = reg_or_mem(&mut reader, mod_reg_rm, width)?;
+            (Opcode::Test, [rm, reg], width)
+        }
+        // reg/mem to/from register
+        0x88..=0x8b => {
+            let width = width(op);
+            let mod_reg_rm = reader.byte()?;
+            let reg = register(mod_reg_rm >> 3, width);
+            let rm = reg_or_mem(&mut reader, mod_reg_rm, width)?;
+            if op & REG_IS_DEST == REG_IS_DEST {
+                (Opcode::Mov, [reg, rm], width)
+            } else {
+                (Opcode::Mov, [rm, reg], width)
+            }
+        }
+        // segment register to/from reg/mem
+        0x8c | 0x8e => {
+            let mod_reg_rm = reader.byte()?;
+            if mod_reg_rm & 0b0010_0000 != 0 {
+                return Err(reader.unknown(op));
+            }
+            let segment = Operand::Register(segment_register(mod_reg_rm >> 3));
+            let rm = reg_or_mem(&mut reader, mod_reg_rm, Width::Word)?;
+            if op & REG_IS_DEST == REG_IS_DEST {
+                (Opcode::Mov, [segment, rm], Width::Word)
+            } else {
+                (Opcode::Mov, [rm, segment], Width::Word)
+            }
+        }
+        // reg/mem from the stack
+        0x8f => {
+            let mod_reg_rm = reader.byte()?;
+            if mod_reg_rm & 0b0011_1000 != 0 {
+                return Err(reader.unknown(op));
+            }
+            let destination = reg_or_mem(&mut reader, mod_reg_rm, Width::Word)?;
+            (Opcode::Pop, [destination, Operand::None], Width::Word)
+        }
+        0x9a => {
+            let offset = reader.word()?;
+            let segment = reader.word()?;
+            (
+                Opcode::CallFar,
+                [Operand::Far { segment, offset }, Operand::None],
+                Width::Word,
+            )
+        }
+        0x9c => (Opcode::Pushf, [Operand::None, Operand::None], Width::Word),
+        0x9d => (Opcode::Popf, [Operand::None, Operand::None], Width::Word),
+        // memory to/from accumulator
+        0xa0..=0xa3 => {
+            let width = width(op);
+            let address = Operand::Memory(EffectiveAddress {
+                base: Base::Direct,
+                displacement: reader.word()? as i16,
+                displacement_width: Some(Width::Word),
+            });
+            if op & REG_IS_DEST == REG_IS_DEST {
+                (Opcode::Mov, [address, register(0, width)], width)
+            } else {
+                (Opcode::Mov, [register(0, width), address], width)
+            }
+        }
+        // string instructions, which work on ds:si and es:di
+        0xa4..=0xa7 | 0xaa..=0xaf => {
+            let opcode = match op & 0b1111_1110 {
+                0xa4 => Opcode::Movs,
+                0xa6 => Opcode::Cmps,
+                0xaa => Opcode::Stos,
+                0xac => Opcode::Lods,
+                _ => Opcode::Scas,
+            };
+            (opcode, [Operand::None, Operand::None], width(op))
+        }
+        // immediate and accumulator
+        0xa8 | 0xa9 => {
+            let width = width(op);
+            let value = reader.immediate(width)?;
+            (
+                Opcode::Test,
+                [register(0, width), Operand::Immediate(value)],
+                width,
+            )
+        }
+        // immediate to register, wide is different here
+        0xb0..=0xbf => {
+            let width = if op & 0b0000_1000 == 0b0000_1000 {
+                Width::Word
+            } else {
+                Width::Byte
+            };
+            let value = reader.immediate(width)?;
+            (
+                Opcode::Mov,
+                [register(op, width), Operand::Immediate(value)],
+                width,
+            )
+        }
+        // returns, optionally popping some more bytes of arguments
+        0xc2 | 0xca => {
+            let opcode = if op == 0xc2 {
+                Opcode::Ret
+            } else {
+                Opcode::Retf
+            };
+            let value = reader.word()?;
+            (
+                opcode,
+                [Operand::Immediate(value), Operand::None],
+                Width::Word,
+            )
+        }
+        0xc3 => (Opcode::Ret, [Operand::None, Operand::None], Width::Word),
+        0xcb => (Opcode::Retf, [Operand::None, Operand::None], Width::Word),
+        0xcc => (Opcode::Int3, [Operand::None, Operand::None], Width::Byte),
+        0xcd => {
+            let vector = reader.byte()?;
+            (
+                Opcode::Int,
+                [Operand::Immediate(vector as u16), Operand::None],
+                Width::Byte,
+            )
+        }
+        0xce => (Opcode::Into, [Operand::None, Operand::None], Width::Byte),
+        0xcf => (Opcode::Iret, [Operand::None, Operand::None], Width::Word),
+        0xf4 => (Opcode::Hlt, [Operand::None, Operand::None], Width::Byte),
+        0xfa => (Opcode::Cli, [Operand::None, Operand::None], Width::Byte),
+        0xfb => (Opcode::Sti, [Operand::None, Operand::None], Width::Byte),
+        0xfc => (Opcode::Cld, [Operand::None, Operand::None], Width::Byte),
+        0xfd => (Opcode::Std, [Operand::None, Operand::None], Width::Byte),
+        // immediate to reg/mem
+        0xc6 | 0xc7 => {
+            let width = width(op);
+            let mod_reg_rm = reader.byte()?;
+            if mod_reg_rm & 0b0011_1000 != 0 {
+                return Err(reader.unknown(op));
+            }
+            let destination = reg_or_mem(&mut reader, mod_reg_rm, width)?;
+            let value = reader.immediate(width)?;
+            (Opcode::Mov, [destination, Operand::Immediate(value)], width)
+        }
+        // shift/rotate group, by 1 or by cl
+        0xd0..=0xd3 => {
+            let width = width(op);
+            let mod_reg_rm = reader.byte()?;
+            let opcode = match mod_reg_rm & 0b0011_1000 {
+                0b0000_0000 => Opcode::Rol,
+                0b0000_1000 => Opcode::Ror,
+                0b0001_0000 => Opcode::Rcl,
+                0b0001_1000 => Opcode::Rcr,
+                0b0010_0000 => Opcode::Shl,
+                0b0010_1000 => Opcode::Shr,
+                0b0011_1000 => Opcode::Sar,
+                _ => return Err(reader.unknown(op)),
+            };
+            let destination = reg_or_mem(&mut reader, mod_reg_rm, width)?;
+            let count = if op & 0b0000_0010 == 0b0000_0010 {
+                Operand::Register(Registers::_CL)
+            } else {
+                Operand::Immediate(1)
+            };
+            (opcode, [destination, count], width)
+        }
+        0xe0..=0xe3 => {
+            let opcode = match op {
+                0xe0 => Opcode::Loopnz,
+                0xe1 => Opcode::Loopz,
+                0xe2 => Opcode::Loop,
+                _ => Opcode::Jcxz,
+            };
+            let displacement = reader.byte()? as i8 as i16;
+            (
+                opcode,
+                [Operand::Relative(displacement), Operand::None],
+                Width::Byte,
+            )
+        }
+        // reg/mem group, selected by the reg field
+        0xf6 | 0xf7 => {
+            let width = width(op);
+            let mod_reg_rm = reader.byte()?;
+            let opcode = match mod_reg_rm & 0b0011_1000 {
+                0b0000_0000 => Opcode::Test,
+                0b0001_0000 => Opcode::Not,
+                0b0001_1000 => Opcode::Neg,
+                0b0010_0000 => Opcode::Mul,
+                0b0010_1000 => Opcode::Imul,
+                0b0011_0000 => Opcode::Div,
+                0b0011_1000 => Opcode::Idiv,
+                _ => return Err(reader.unknown(op)),
+            };
+            let operand = reg_or_mem(&mut reader, mod_reg_rm, width)?;
+            let source = match opcode {
+                Opcode::Test => Operand::Immediate(reader.immediate(width)?),
+                _ => Operand::None,
+            };
+            (opcode, [operand, source], width)
+        }
+        0xe8 | 0xe9 => {
+            let opcode = if op == 0xe8 {
+                Opcode::Call
+            } else {
+                Opcode::Jmp
+            };
+            let displacement = reader.word()? as i16;
+            (
+                opcode,
+                [Operand::Relative(displacement), Operand::None],
+                Width::Word,
+            )
+        }
+        0xea => {
+            let offset = reader.word()?;
+            let segment = reader.word()?;
+            (
+                Opcode::JmpFar,
+                [Operand::Far { segment, offset }, Operand::None],
+                Width::Word,
+            )
+        }
+        0xeb => {
+            let displacement = reader.byte()? as i8 as i16;
+            (
+                Opcode::Jmp,
+                [Operand::Relative(displacement), Operand::None],
+                Width::Byte,
+            )
+        }
+        0xfe => {
+            let mod_reg_rm = reader.byte()?;
+            let opcode = match mod_reg_rm & 0b0011_1000 {
+                0b0000_0000 => Opcode::Inc,
+                0b0000_1000 => Opcode::Dec,
+                _ => return Err(reader.unknown(op)),
+            };
+            let operand = reg_or_mem(&mut reader, mod_reg_rm, Width::Byte)?;
+            (opcode, [operand, Operand::None], Width::Byte)
+        }
+        // reg/mem group, selected by the reg field
+        0xff => {
+            let mod_reg_rm = reader.byte()?;
+            // a far pointer has to come from memory
+            let far = mod_reg_rm & REG_MODE != REG_MODE;
+            let opcode = match mod_reg_rm & 0b0011_1000 {
+                0b0000_0000 => Opcode::Inc,
+                0b0000_1000 => Opcode::Dec,
+                0b0001_0000 => Opcode::Call,
+                0b0001_1000 if far => Opcode::CallFar,
+                0b0010_0000 => Opcode::Jmp,
+                0b0010_1000 if far => Opcode::JmpFar,
+                0b0011_0000 => Opcode::Push,
+                _ => return Err(reader.unknown(op)),
+            };
+            let operand = reg_or_mem(&mut reader, mod_reg_rm, Width::Word)?;
+            (opcode, [operand, Operand::None], Width::Word)
+        }
+        _ => return Err(reader.unknown(op)),
+    };
+
+    // forms with the same effect as a shorter one, for the accumulator or any
+    // register, that NASM always picks instead
+    let long_form = matches!(
+        (op, operands),
+        (0x82, _)
+            | (
+                0x80 | 0x81 | 0xf6 | 0xf7,
+                [
+                    Operand::Register(Registers::_AL | Registers::_AX),
+                    Operand::Immediate(_)
+                ]
+            )
+            | (0xc6 | 0xc7, [Operand::Register(_), _])
+    );
+    let long_form = long_form.then(|| {
+        let mut encoding = Encoding {
+            bytes: [0; 6],
+            length: reader.position - start,
+        };
+        encoding.bytes[..encoding.length].copy_from_slice(&buffer[start..reader.position]);
+        encoding
+    });
+
+    Ok(Instruction {
+        opcode,
+        operands,
+        width,
+        length: reader.position - offset,
+        prefixes,
+        immediate_width: reader.immediate,
+        long_form,
+    })
+}
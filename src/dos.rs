@@ -0,0 +1,333 @@
+use crate::{
+    decode::Width,
+    error::SimError,
+    interrupt::InterruptHandler,
+    machine::Machine,
+    tables::{CARRY_FLAG, Registers},
+};
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io::{BufRead, ErrorKind, Read, Write},
+    path::{Component, Path, PathBuf},
+};
+
+// DOS error codes, given back in ax with the carry flag set
+const FILE_NOT_FOUND: u16 = 0x02;
+const PATH_NOT_FOUND: u16 = 0x03;
+const TOO_MANY_OPEN_FILES: u16 = 0x04;
+const ACCESS_DENIED: u16 = 0x05;
+const INVALID_HANDLE: u16 = 0x06;
+const INVALID_ACCESS: u16 = 0x0c;
+
+const STDIN: u16 = 0;
+// after stdin, stdout, stderr, aux and prn
+const FIRST_FILE_HANDLE: u16 = 5;
+
+// what a read from the console gives back once the input runs out
+const END_OF_FILE: u8 = 0x1a;
+const CARRIAGE_RETURN: u8 = 0x0d;
+
+/// The date and time DOS reports, fixed so runs are repeatable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Clock {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+    pub hundredths: u8,
+}
+
+impl Default for Clock {
+    // the start of DOS time
+    fn default() -> Self {
+        Self::new(1980, 1, 1, 0, 0, 0)
+    }
+}
+
+impl Clock {
+    pub fn new(year: u16, month: u8, day: u8, hour: u8, minute: u8, second: u8) -> Self {
+        Self {
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            second,
+            hundredths: 0,
+        }
+    }
+
+    // 0 for sunday, by Sakamoto's method. January and February count as the
+    // year before, which for year 0 is -1.
+    fn weekday(&self) -> u8 {
+        const OFFSETS: [i32; 12] = [0, 3, 2, 5, 0, 3, 5, 1, 4, 6, 2, 4];
+        let year = self.year as i32 - (self.month < 3) as i32;
+        let month = (self.month.clamp(1, 12) - 1) as usize;
+        (year + year.div_euclid(4) - year.div_euclid(100)
+            + year.div_euclid(400)
+            + OFFSETS[month]
+            + self.day as i32)
+            .rem_euclid(7) as u8
+    }
+}
+
+/// Stands in for the parts of DOS small .COM programs use: console input and
+/// output, exiting, the date and time, and files. Files are only ever looked
+/// for in the sandbox directory, and without one every file call is refused.
+pub struct Dos {
+    input: Box<dyn BufRead>,
+    output: Box<dyn Write>,
+    pub clock: Clock,
+    pub sandbox: Option<PathBuf>,
+    files: HashMap<u16, File>,
+}
+
+impl Dos {
+    pub fn new(input: impl BufRead + 'static, output: impl Write + 'static) -> Self {
+        Self {
+            input: Box::new(input),
+            output: Box::new(output),
+            clock: Clock::default(),
+            sandbox: None,
+            files: HashMap::new(),
+        }
+    }
+
+    /// Has the machine call on this for int 21h, and for int 20h's plain
+    /// exit.
+    pub fn install(self, machine: &mut Machine) {
+        machine.set_interrupt_handler(0x20, |machine: &mut Machine, _| {
+            machine.terminate(0);
+            Ok(())
+        });
+        machine.set_interrupt_handler(0x21, self);
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<(), SimError> {
+        self.output.write_all(bytes)?;
+        self.output.flush()?;
+        Ok(())
+    }
+
+    // One byte of console input, with enter coming through as a carriage
+    // return like it does on DOS
+    fn read_char(&mut self) -> Result<u8, SimError> {
+        let mut byte = [0];
+        Ok(match self.input.read(&mut byte)? {
+            0 => END_OF_FILE,
+            _ if byte[0] == b'\n' => CARRIAGE_RETURN,
+            _ => byte[0],
+        })
+    }
+
+    // AH=0Ah: a line into the buffer at ds:dx, which starts with how many
+    // characters fit, carriage return included. The count read goes after it,
+    // then the line itself.
+    fn read_line(&mut self, machine: &mut Machine) -> Result<(), SimError> {
+        let (segment, buffer) = ds_dx(machine);
+        let room = machine.read_memory(segment, buffer, Width::Byte) as usize;
+        if room == 0 {
+            return Ok(());
+        }
+
+        let mut line = String::new();
+        self.input.read_line(&mut line)?;
+        let line = line.trim_end_matches(['\r', '\n']).as_bytes();
+        let line = &line[..line.len().min(room - 1)];
+
+        machine.write_memory(
+            segment,
+            buffer.wrapping_add(1),
+            Width::Byte,
+            line.len() as u16,
+        )?;
+        let end = CARRIAGE_RETURN;
+        for (offset, byte) in (2..).zip(line.iter().chain([&end])) {
+            machine.write_memory(
+                segment,
+                buffer.wrapping_add(offset),
+                Width::Byte,
+                *byte as u16,
+            )?;
+        }
+        Ok(())
+    }
+
+    // Where a name the program gave lives on the host: somewhere under the
+    // sandbox, without any way to climb out of it
+    fn path(&self, name: &[u8]) -> Result<PathBuf, u16> {
+        let sandbox = self.sandbox.as_ref().ok_or(ACCESS_DENIED)?;
+        let name = String::from_utf8_lossy(name).replace('\\', "/");
+        let relative = Path::new(&name);
+        let plain = relative
+            .components()
+            .all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+        if name.is_empty() || name.contains(':') || !plain {
+            return Err(PATH_NOT_FOUND);
+        }
+        Ok(sandbox.join(relative))
+    }
+
+    // The lowest handle that's not in use
+    fn open(&mut self, file: File) -> Result<u16, u16> {
+        let handle = (FIRST_FILE_HANDLE..=u16::MAX)
+            .find(|handle| !self.files.contains_key(handle))
+            .ok_or(TOO_MANY_OPEN_FILES)?;
+        self.files.insert(handle, file);
+        Ok(handle)
+    }
+
+    // AH=3Ch and 3Dh: a handle for the file named at ds:dx, created (or
+    // emptied) for 3Ch, opened as al says for 3Dh
+    fn open_file(&mut self, machine: &Machine, create: bool) -> Result<u16, u16> {
+        let (segment, name) = ds_dx(machine);
+        let path = self.path(&asciiz(machine, segment, name))?;
+        let mut options = OpenOptions::new();
+        if create {
+            options.read(true).write(true).create(true).truncate(true);
+        } else {
+            match machine.cpu.get_value(Registers::_AL) & 0b111 {
+                0 => options.read(true),
+                1 => options.write(true),
+                2 => options.read(true).write(true),
+                _ => return Err(INVALID_ACCESS),
+            };
+        }
+        let file = options.open(path).map_err(error_code)?;
+        self.open(file)
+    }
+
+    // AH=3Fh: up to cx bytes from handle bx into ds:dx
+    fn read_file(&mut self, machine: &mut Machine) -> Result<Result<u16, u16>, SimError> {
+        let handle = machine.cpu.get_value(Registers::_BX);
+        let mut bytes = vec![0; machine.cpu.get_value(Registers::_CX) as usize];
+        let read = match handle {
+            STDIN => self.input.read(&mut bytes).map_err(error_code),
+            _ => match self.files.get_mut(&handle) {
+                Some(file) => file.read(&mut bytes).map_err(error_code),
+                None => Err(INVALID_HANDLE),
+            },
+        };
+        let read = match read {
+            Ok(read) => read,
+            Err(code) => return Ok(Err(code)),
+        };
+
+        let (segment, buffer) = ds_dx(machine);
+        for (offset, byte) in (0..).zip(&bytes[..read]) {
+            machine.write_memory(
+                segment,
+                buffer.wrapping_add(offset),
+                Width::Byte,
+                *byte as u16,
+            )?;
+        }
+        Ok(Ok(read as u16))
+    }
+}
+
+impl InterruptHandler for Dos {
+    fn interrupt(&mut self, machine: &mut Machine, vector: u8) -> Result<(), SimError> {
+        let function = machine.cpu.get_value(Registers::_AH) as u8;
+        match function {
+            0x00 => machine.terminate(0),
+            // echoed, as it would be typing it
+            0x01 => {
+                let char = self.read_char()?;
+                self.write(&[char])?;
+                machine.cpu.set(Registers::_AL, char as u16);
+            }
+            0x02 => {
+                let char = machine.cpu.get_value(Registers::_DL) as u8;
+                self.write(&[char])?;
+            }
+            // everything up to a '$'
+            0x09 => {
+                let (segment, offset) = ds_dx(machine);
+                let string = terminated(machine, segment, offset, b'$');
+                self.write(&string)?;
+                machine.cpu.set(Registers::_AL, b'$' as u16);
+            }
+            0x0a => self.read_line(machine)?,
+            0x2a => {
+                let clock = self.clock;
+                machine.cpu.set(Registers::_CX, clock.year);
+                machine.cpu.set(Registers::_DH, clock.month as u16);
+                machine.cpu.set(Registers::_DL, clock.day as u16);
+                machine.cpu.set(Registers::_AL, clock.weekday() as u16);
+            }
+            0x2c => {
+                let clock = self.clock;
+                machine.cpu.set(Registers::_CH, clock.hour as u16);
+                machine.cpu.set(Registers::_CL, clock.minute as u16);
+                machine.cpu.set(Registers::_DH, clock.second as u16);
+                machine.cpu.set(Registers::_DL, clock.hundredths as u16);
+            }
+            0x3c | 0x3d => {
+                let handle = self.open_file(machine, function == 0x3c);
+                finish(machine, handle);
+            }
+            0x3e => {
+                let handle = machine.cpu.get_value(Registers::_BX);
+                let closed = match self.files.remove(&handle) {
+                    Some(_) => Ok(0),
+                    None => Err(INVALID_HANDLE),
+                };
+                finish(machine, closed);
+            }
+            0x3f => {
+                let read = self.read_file(machine)?;
+                finish(machine, read);
+            }
+            0x4c => {
+                let exit_code = machine.cpu.get_value(Registers::_AL) as u8;
+                machine.terminate(exit_code);
+            }
+            _ => return Err(SimError::UnsupportedInterrupt { vector, function }),
+        }
+        Ok(())
+    }
+}
+
+fn ds_dx(machine: &Machine) -> (u16, u16) {
+    (
+        machine.cpu.get_value(Registers::_DS),
+        machine.cpu.get_value(Registers::_DX),
+    )
+}
+
+// The bytes at segment:offset up to, but not including, the terminator
+fn terminated(machine: &Machine, segment: u16, offset: u16, terminator: u8) -> Vec<u8> {
+    (0..=u16::MAX)
+        .map(|i| machine.read_memory(segment, offset.wrapping_add(i), Width::Byte) as u8)
+        .take_while(|byte| *byte != terminator)
+        .collect()
+}
+
+fn asciiz(machine: &Machine, segment: u16, offset: u16) -> Vec<u8> {
+    terminated(machine, segment, offset, 0)
+}
+
+// Files report how they went in ax, with the carry flag set if it's an error
+fn finish(machine: &mut Machine, outcome: Result<u16, u16>) {
+    let (ax, failed) = match outcome {
+        Ok(value) => (value, false),
+        Err(code) => (code, true),
+    };
+    machine.cpu.set(Registers::_AX, ax);
+    if failed {
+        machine.cpu.flags |= CARRY_FLAG;
+    } else {
+        machine.cpu.flags &= !CARRY_FLAG;
+    }
+}
+
+fn error_code(error: std::io::Error) -> u16 {
+    match error.kind() {
+        ErrorKind::NotFound => FILE_NOT_FOUND,
+        _ => ACCESS_DENIED,
+    }
+}
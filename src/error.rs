@@ -0,0 +1,68 @@
+use crate::{decode::DecodeError, loader::LoadError};
+use std::fmt::Display;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SimError {
+    TruncatedInstruction { offset: usize },
+    UnknownOpcode { byte: u8, offset: usize },
+    MemoryFault { address: usize },
+    // ROM is read only
+    RomWrite { address: usize },
+    // jumped somewhere other than the loaded program or just past its end
+    OutsideProgram { address: usize },
+    // an interrupt function the host stands in for, but not this one
+    UnsupportedInterrupt { vector: u8, function: u8 },
+    // the host's side of an interrupt failed
+    Io { message: String },
+    Load { error: LoadError },
+}
+
+impl Display for SimError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::TruncatedInstruction { offset } => {
+                write!(f, "truncated instruction at offset {offset:#x}")
+            }
+            Self::UnknownOpcode { byte, offset } => {
+                write!(f, "unknown opcode {byte:#04x} at offset {offset:#x}")
+            }
+            Self::MemoryFault { address } => {
+                write!(f, "memory fault at address {address:#x}")
+            }
+            Self::RomWrite { address } => write!(f, "write to rom at address {address:#x}"),
+            Self::OutsideProgram { address } => {
+                write!(f, "executing outside the program at address {address:#x}")
+            }
+            Self::UnsupportedInterrupt { vector, function } => {
+                write!(f, "unsupported int {vector:#04x} function {function:#04x}")
+            }
+            Self::Io { message } => write!(f, "i/o error: {message}"),
+            Self::Load { error } => write!(f, "can't load the program: {error}"),
+        }
+    }
+}
+
+impl std::error::Error for SimError {}
+
+impl From<std::io::Error> for SimError {
+    fn from(error: std::io::Error) -> Self {
+        Self::Io {
+            message: error.to_string(),
+        }
+    }
+}
+
+impl From<LoadError> for SimError {
+    fn from(error: LoadError) -> Self {
+        Self::Load { error }
+    }
+}
+
+impl From<DecodeError> for SimError {
+    fn from(error: DecodeError) -> Self {
+        match error {
+            DecodeError::TruncatedInstruction { offset } => Self::TruncatedInstruction { offset },
+            DecodeError::UnknownOpcode { byte, offset } => Self::UnknownOpcode { byte, offset },
+        }
+    }
+}
@@ -0,0 +1,17 @@
+use crate::{error::SimError, machine::Machine};
+
+/// Services an interrupt on the host, in place of whatever 8086 code the
+/// vector table points at. Execution carries on after the instruction that
+/// raised it, with whatever the handler left in the registers and memory.
+pub trait InterruptHandler {
+    fn interrupt(&mut self, machine: &mut Machine, vector: u8) -> Result<(), SimError>;
+}
+
+impl<F> InterruptHandler for F
+where
+    F: FnMut(&mut Machine, u8) -> Result<(), SimError>,
+{
+    fn interrupt(&mut self, machine: &mut Machine, vector: u8) -> Result<(), SimError> {
+        self(machine, vector)
+    }
+}
@@ -0,0 +1,386 @@
+use crate::{
+    decode::Width,
+    error::SimError,
+    machine::{Machine, physical_address},
+    tables::Registers,
+};
+use std::fmt::Display;
+
+// Where a .COM program starts, past its program segment prefix
+const COM_OFFSET: u16 = 0x100;
+// The segment just past the memory DOS hands a program, the top of the
+// conventional 640k
+const MEMORY_TOP: u16 = 0xa000;
+
+// Offsets into the program segment prefix
+const PSP_EXIT: u16 = 0x00;
+const PSP_MEMORY_TOP: u16 = 0x02;
+const PSP_DOS_CALL: u16 = 0x50;
+const PSP_COMMAND_TAIL: u16 = 0x80;
+// a length byte, then the tail and the carriage return after it
+const COMMAND_TAIL_SIZE: usize = 0x80;
+
+const CARRIAGE_RETURN: u8 = 0x0d;
+
+// What an .EXE starts with, or the other way round for some old linkers
+const EXE_SIGNATURES: [u16; 2] = [0x5a4d, 0x4d5a];
+const EXE_HEADER_SIZE: usize = 0x1c;
+const PAGE_SIZE: usize = 512;
+const PARAGRAPH_SIZE: usize = 16;
+// A program segment prefix's worth of paragraphs
+const PSP_PARAGRAPHS: u16 = 0x10;
+
+// Intel HEX record types
+const HEX_DATA: u8 = 0x00;
+const HEX_END: u8 = 0x01;
+const HEX_SEGMENT: u8 = 0x02;
+const HEX_START: u8 = 0x03;
+
+/// Why a program's file can't be loaded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadError {
+    // shorter than the fixed part of an .EXE header
+    TruncatedHeader { length: usize },
+    BadSignature { signature: u16 },
+    // the file's size in pages, and the bytes used in the last one, don't
+    // make sense, or leave no room for the header
+    BadImageSize { pages: u16, last_page: u16 },
+    // the header says the file is longer than it is
+    TruncatedImage { expected: usize, length: usize },
+    // the relocation table runs off the end of the file
+    BadRelocationTable { offset: u16, count: u16 },
+    // a relocation points outside the image
+    BadRelocation { segment: u16, offset: u16 },
+    // too big for where it's meant to go
+    TooLarge { size: usize },
+    // a line of an Intel HEX file that isn't a record, counting from 1
+    BadHexRecord { line: usize },
+    BadHexChecksum { line: usize },
+    // a record type the 8086's addressing has no use for
+    UnsupportedHexRecord { line: usize, kind: u8 },
+    // nothing to load
+    NoHexData,
+}
+
+impl Display for LoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::TruncatedHeader { length } => {
+                write!(f, "only {length} bytes, too short for an exe header")
+            }
+            Self::BadSignature { signature } => {
+                write!(f, "bad exe signature {signature:#06x}")
+            }
+            Self::BadImageSize { pages, last_page } => write!(
+                f,
+                "bad exe size of {pages} pages with {last_page} bytes in the last"
+            ),
+            Self::TruncatedImage { expected, length } => {
+                write!(f, "exe should be {expected} bytes long but is {length}")
+            }
+            Self::BadRelocationTable { offset, count } => write!(
+                f,
+                "relocation table of {count} entries at {offset:#x} runs off the end"
+            ),
+            Self::BadRelocation { segment, offset } => {
+                write!(
+                    f,
+                    "relocation at {segment:04x}:{offset:04x} is outside the image"
+                )
+            }
+            Self::TooLarge { size } => write!(f, "program of {size} bytes is too large"),
+            Self::BadHexRecord { line } => write!(f, "bad hex record on line {line}"),
+            Self::BadHexChecksum { line } => write!(f, "bad hex checksum on line {line}"),
+            Self::UnsupportedHexRecord { line, kind } => {
+                write!(f, "unsupported hex record type {kind:02x} on line {line}")
+            }
+            Self::NoHexData => write!(f, "no data records in the hex file"),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+/// The fixed part of a DOS .EXE's MZ header, with the `e_` names from
+/// Microsoft's definition alongside.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExeHeader {
+    // e_cblp, where 0 means all 512
+    pub last_page_bytes: u16,
+    // e_cp, the last one counted even if it's partial
+    pub pages: u16,
+    // e_crlc
+    pub relocations: u16,
+    // e_cparhdr
+    pub header_paragraphs: u16,
+    // e_minalloc and e_maxalloc, the memory wanted past the image
+    pub min_paragraphs: u16,
+    pub max_paragraphs: u16,
+    // e_ss and e_sp, ss relative to the start of the image
+    pub ss: u16,
+    pub sp: u16,
+    // e_csum
+    pub checksum: u16,
+    // e_ip and e_cs, cs relative to the start of the image
+    pub ip: u16,
+    pub cs: u16,
+    // e_lfarlc, from the start of the file
+    pub relocation_table: u16,
+    // e_ovno
+    pub overlay: u16,
+}
+
+impl ExeHeader {
+    pub fn parse(file: &[u8]) -> Result<Self, LoadError> {
+        if file.len() < EXE_HEADER_SIZE {
+            return Err(LoadError::TruncatedHeader { length: file.len() });
+        }
+        let word = |index: usize| u16::from_le_bytes([file[index * 2], file[index * 2 + 1]]);
+        if !EXE_SIGNATURES.contains(&word(0)) {
+            return Err(LoadError::BadSignature { signature: word(0) });
+        }
+
+        let header = Self {
+            last_page_bytes: word(1),
+            pages: word(2),
+            relocations: word(3),
+            header_paragraphs: word(4),
+            min_paragraphs: word(5),
+            max_paragraphs: word(6),
+            ss: word(7),
+            sp: word(8),
+            checksum: word(9),
+            ip: word(10),
+            cs: word(11),
+            relocation_table: word(12),
+            overlay: word(13),
+        };
+
+        let bad_size = LoadError::BadImageSize {
+            pages: header.pages,
+            last_page: header.last_page_bytes,
+        };
+        if header.pages == 0
+            || header.last_page_bytes as usize >= PAGE_SIZE
+            || header.file_size() < header.header_size()
+        {
+            return Err(bad_size);
+        }
+        if header.file_size() > file.len() {
+            return Err(LoadError::TruncatedImage {
+                expected: header.file_size(),
+                length: file.len(),
+            });
+        }
+        let table_end = header.relocation_table as usize + header.relocations as usize * 4;
+        if header.relocations != 0 && table_end > file.len() {
+            return Err(LoadError::BadRelocationTable {
+                offset: header.relocation_table,
+                count: header.relocations,
+            });
+        }
+        Ok(header)
+    }
+
+    /// How much of the file the header covers, image included.
+    pub fn file_size(&self) -> usize {
+        let partial = match self.last_page_bytes {
+            0 => 0,
+            bytes => PAGE_SIZE - bytes as usize,
+        };
+        self.pages as usize * PAGE_SIZE - partial
+    }
+
+    pub fn header_size(&self) -> usize {
+        self.header_paragraphs as usize * PARAGRAPH_SIZE
+    }
+}
+
+/// Loads a DOS .COM program the way DOS does: at segment:0100h, after a
+/// program segment prefix holding the command tail, with every segment
+/// register set to the segment and the stack at the top of it. The tail is
+/// what followed the program's name on the command line, usually starting
+/// with a space, and is cut short at 126 bytes. Like any DOS program, it runs
+/// until it terminates or halts.
+pub fn load_com(
+    machine: &mut Machine,
+    segment: u16,
+    image: &[u8],
+    command_tail: &[u8],
+) -> Result<(), SimError> {
+    // it has to fit in the one segment, below the word left on the stack
+    if image.len() > (0xfffe - COM_OFFSET) as usize {
+        return Err(LoadError::TooLarge { size: image.len() }.into());
+    }
+    machine.load_at(segment, COM_OFFSET, image)?;
+    write_psp(machine, segment, command_tail)?;
+
+    for register in [Registers::_DS, Registers::_ES, Registers::_SS] {
+        machine.cpu.set(register, segment);
+    }
+    // with a zero on the stack, so a plain ret goes to the int 20h at the
+    // start of the prefix
+    machine.cpu.set(Registers::_SP, 0xfffe);
+    machine.write_memory(segment, 0xfffe, Width::Word, 0)?;
+    // it's done when it says so
+    machine.run_anywhere();
+    Ok(())
+}
+
+/// Loads a DOS .EXE program the way DOS does: its image just after a program
+/// segment prefix at segment:0000, with its relocations fixed up for where
+/// the image landed. cs:ip and ss:sp come from the header, while ds and es
+/// point at the prefix. The tail, and how long it runs, are as for
+/// [`load_com`].
+pub fn load_exe(
+    machine: &mut Machine,
+    segment: u16,
+    file: &[u8],
+    command_tail: &[u8],
+) -> Result<(), SimError> {
+    let header = ExeHeader::parse(file)?;
+    let mut image = file[header.header_size()..header.file_size()].to_vec();
+    let start = segment.wrapping_add(PSP_PARAGRAPHS);
+
+    // each one the offset, then the segment, of a word in the image that
+    // holds a segment relative to the start of the image
+    let table = file
+        .get(header.relocation_table as usize..)
+        .unwrap_or_default();
+    for entry in table.chunks_exact(4).take(header.relocations as usize) {
+        let (offset, relocation_segment) = (
+            u16::from_le_bytes([entry[0], entry[1]]),
+            u16::from_le_bytes([entry[2], entry[3]]),
+        );
+        let address = relocation_segment as usize * PARAGRAPH_SIZE + offset as usize;
+        let word = image
+            .get_mut(address..address + 2)
+            .ok_or(LoadError::BadRelocation {
+                segment: relocation_segment,
+                offset,
+            })?;
+        let value = u16::from_le_bytes([word[0], word[1]]).wrapping_add(start);
+        word.copy_from_slice(&value.to_le_bytes());
+    }
+
+    machine.load_at(start, 0, &image)?;
+    write_psp(machine, segment, command_tail)?;
+
+    machine
+        .cpu
+        .set(Registers::_CS, start.wrapping_add(header.cs));
+    machine.cpu.ip = header.ip;
+    machine
+        .cpu
+        .set(Registers::_SS, start.wrapping_add(header.ss));
+    machine.cpu.set(Registers::_SP, header.sp);
+    machine.cpu.set(Registers::_DS, segment);
+    machine.cpu.set(Registers::_ES, segment);
+    machine.run_anywhere();
+    Ok(())
+}
+
+/// Loads an Intel HEX file's data records into memory wherever they say, and
+/// starts at cs:ip from its start segment address record. Without one it
+/// starts at the lowest address loaded, as segment:offset the way the file
+/// gave it. Records past the end of file record are ignored.
+pub fn load_hex(machine: &mut Machine, text: &str) -> Result<(), SimError> {
+    let mut bytes = Vec::new();
+    let mut segment = 0;
+    let mut start = None;
+
+    for (line, record) in (1..).zip(text.lines()) {
+        let record = record.trim();
+        if record.is_empty() {
+            continue;
+        }
+        let (offset, kind, data) = hex_record(line, record)?;
+        match (kind, data.len()) {
+            (HEX_DATA, _) => bytes.extend(
+                (0..)
+                    .zip(data)
+                    .map(|(i, byte)| ((segment, offset.wrapping_add(i)), byte)),
+            ),
+            (HEX_END, _) => break,
+            (HEX_SEGMENT, 2) => segment = u16::from_be_bytes([data[0], data[1]]),
+            (HEX_START, 4) => {
+                start = Some((
+                    u16::from_be_bytes([data[0], data[1]]),
+                    u16::from_be_bytes([data[2], data[3]]),
+                ));
+            }
+            (HEX_SEGMENT | HEX_START, _) => return Err(LoadError::BadHexRecord { line }.into()),
+            _ => return Err(LoadError::UnsupportedHexRecord { line, kind }.into()),
+        }
+    }
+
+    // as one image, with anything between the records zeroed, loaded at the
+    // segment:offset the lowest byte was written with
+    let physical = |((segment, offset), _): &((u16, u16), u8)| physical_address(*segment, *offset);
+    let (Some(&(origin, _)), Some(highest)) = (
+        bytes.iter().min_by_key(|byte| physical(byte)),
+        bytes.iter().map(physical).max(),
+    ) else {
+        return Err(LoadError::NoHexData.into());
+    };
+    let lowest = physical_address(origin.0, origin.1);
+    let mut image = vec![0; highest + 1 - lowest];
+    for byte in &bytes {
+        image[physical(byte) - lowest] = byte.1;
+    }
+    machine.load_at(origin.0, origin.1, &image)?;
+
+    if let Some((cs, ip)) = start {
+        machine.cpu.set(Registers::_CS, cs);
+        machine.cpu.ip = ip;
+    }
+    Ok(())
+}
+
+// A record's address, type and data, once its checksum's been checked
+fn hex_record(line: usize, record: &str) -> Result<(u16, u8, Vec<u8>), LoadError> {
+    let bad = LoadError::BadHexRecord { line };
+    let digits = record.strip_prefix(':').ok_or(bad)?;
+    if digits.len() % 2 != 0 {
+        return Err(bad);
+    }
+    let bytes = (0..digits.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(digits.get(i..i + 2)?, 16).ok())
+        .collect::<Option<Vec<_>>>()
+        .ok_or(bad)?;
+    // the length, address and type, then the data, then the checksum
+    if bytes.len() < 5 || bytes.len() != bytes[0] as usize + 5 {
+        return Err(bad);
+    }
+    if bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) != 0 {
+        return Err(LoadError::BadHexChecksum { line });
+    }
+    Ok((
+        u16::from_be_bytes([bytes[1], bytes[2]]),
+        bytes[3],
+        bytes[4..bytes.len() - 1].to_vec(),
+    ))
+}
+
+// The parts of the program segment prefix programs go looking at
+fn write_psp(machine: &mut Machine, segment: u16, command_tail: &[u8]) -> Result<(), SimError> {
+    let mut bytes = |offset: u16, bytes: &[u8]| {
+        (offset..).zip(bytes).try_for_each(|(offset, byte)| {
+            machine.write_memory(segment, offset, Width::Byte, *byte as u16)
+        })
+    };
+
+    // int 20h
+    bytes(PSP_EXIT, &[0xcd, 0x20])?;
+    bytes(PSP_MEMORY_TOP, &MEMORY_TOP.to_le_bytes())?;
+    // int 21h, retf
+    bytes(PSP_DOS_CALL, &[0xcd, 0x21, 0xcb])?;
+
+    let tail = &command_tail[..command_tail.len().min(COMMAND_TAIL_SIZE - 2)];
+    let mut field = vec![tail.len() as u8];
+    field.extend(tail);
+    field.push(CARRIAGE_RETURN);
+    field.resize(COMMAND_TAIL_SIZE, 0);
+    bytes(PSP_COMMAND_TAIL, &field)
+}
@@ -0,0 +1,685 @@
+use crate::{
+    cpu::Cpu,
+    decode::{
+        Base, EffectiveAddress, Instruction, Opcode, Operand, Prefixes, Repeat, Width, decode,
+    },
+    error::SimError,
+    interrupt::InterruptHandler,
+    tables::{
+        BP, BX, CARRY_FLAG, DEFINED_FLAGS, DI, DIRECTION_FLAG, INTERRUPT_FLAG, OVERFLOW_FLAG,
+        PARITY_FLAG, RESERVED_FLAGS, Registers, SI, SIGN_FLAG, TRAP_FLAG, WIDE_REGISTER_TABLE,
+        ZERO_FLAG,
+    },
+    trace,
+};
+use std::collections::HashMap;
+
+// A mb of memory
+const MEMORY_SIZE: usize = 1024 * 1024;
+
+// Raised by div and idiv when the quotient won't fit
+const DIVIDE_ERROR: u8 = 0;
+// Raised by int3
+const BREAKPOINT: u8 = 3;
+// Raised by into when the overflow flag is set
+const OVERFLOW: u8 = 4;
+
+// Where segment:offset lands, wrapping around the top of memory like the
+// 8086's 20 address lines do
+pub(crate) fn physical_address(segment: u16, offset: u16) -> usize {
+    (((segment as usize) << 4) + offset as usize) % MEMORY_SIZE
+}
+
+pub struct Machine {
+    pub cpu: Cpu,
+    memory: Box<[u8]>,
+    // the loaded program runs until ip walks off the end of it, and going
+    // anywhere else outside it is an error
+    code_start: usize,
+    code_end: usize,
+    // by vector, run on the host instead of going through the vector table
+    handlers: HashMap<u8, Box<dyn InterruptHandler>>,
+    // set once the program has asked to stop
+    exit_code: Option<u8>,
+    // stopped by hlt, with nothing to interrupt it
+    halted: bool,
+    // everything from here to the top of memory is read only
+    rom_start: usize,
+}
+
+impl std::fmt::Debug for Machine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut handlers: Vec<_> = self.handlers.keys().collect();
+        handlers.sort();
+        f.debug_struct("Machine")
+            .field("cpu", &self.cpu)
+            .field("code_start", &self.code_start)
+            .field("code_end", &self.code_end)
+            .field("handlers", &handlers)
+            .field("exit_code", &self.exit_code)
+            .field("halted", &self.halted)
+            .field("rom_start", &self.rom_start)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for Machine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Machine {
+    pub fn new() -> Self {
+        Self {
+            cpu: Cpu::new(),
+            memory: vec![0; MEMORY_SIZE].into_boxed_slice(),
+            code_start: 0,
+            code_end: 0,
+            handlers: HashMap::new(),
+            exit_code: None,
+            halted: false,
+            rom_start: MEMORY_SIZE,
+        }
+    }
+
+    /// Has the host service an interrupt vector from now on, replacing
+    /// anything already registered for it.
+    pub fn set_interrupt_handler(&mut self, vector: u8, handler: impl InterruptHandler + 'static) {
+        self.handlers.insert(vector, Box::new(handler));
+    }
+
+    /// Copies a program into memory at address 0 and points cs:ip at its
+    /// first byte. Set `cpu.ip` afterwards to start somewhere else.
+    pub fn load(&mut self, program: &[u8]) -> Result<(), SimError> {
+        self.copy_program(0, program)?;
+        self.cpu.set(Registers::_CS, 0);
+        self.cpu.ip = 0;
+        Ok(())
+    }
+
+    /// Copies a program into memory at segment:offset and points cs:ip at its
+    /// first byte. It mustn't run past the top of memory.
+    pub fn load_at(&mut self, segment: u16, offset: u16, program: &[u8]) -> Result<(), SimError> {
+        self.copy_program(physical_address(segment, offset), program)?;
+        self.cpu.set(Registers::_CS, segment);
+        self.cpu.ip = offset;
+        Ok(())
+    }
+
+    fn copy_program(&mut self, address: usize, program: &[u8]) -> Result<(), SimError> {
+        let end = address + program.len();
+        // only a mapped rom refuses it, the top of memory faults as ever
+        if self.rom_start < MEMORY_SIZE && end > self.rom_start {
+            return Err(SimError::RomWrite {
+                address: address.max(self.rom_start),
+            });
+        }
+        self.memory
+            .get_mut(address..end)
+            .ok_or(SimError::MemoryFault {
+                address: MEMORY_SIZE,
+            })?
+            .copy_from_slice(program);
+        (self.code_start, self.code_end) = (address, end);
+        self.exit_code = None;
+        self.halted = false;
+        Ok(())
+    }
+
+    /// Maps a ROM image at the very top of memory, where anything written to
+    /// it is refused.
+    pub fn map_rom(&mut self, image: &[u8]) -> Result<(), SimError> {
+        let start = MEMORY_SIZE
+            .checked_sub(image.len())
+            .ok_or(SimError::MemoryFault {
+                address: MEMORY_SIZE,
+            })?;
+        self.memory[start..].copy_from_slice(image);
+        self.rom_start = start;
+        Ok(())
+    }
+
+    /// Puts the machine in the state an 8086 comes out of reset in, with
+    /// everything cleared but cs, and running from ffff:0000. It runs as if
+    /// [`run_anywhere`](Self::run_anywhere) had been called.
+    pub fn reset(&mut self) {
+        self.cpu = Cpu::new();
+        self.cpu.set(Registers::_CS, 0xffff);
+        self.run_anywhere();
+        self.exit_code = None;
+        self.halted = false;
+    }
+
+    /// Lets the loaded program go wherever in memory it likes, running until
+    /// it halts or terminates rather than until it walks off its end.
+    pub fn run_anywhere(&mut self) {
+        (self.code_start, self.code_end) = (0, MEMORY_SIZE);
+    }
+
+    /// Stops the machine after the current instruction, the way a program
+    /// exiting does.
+    pub fn terminate(&mut self, exit_code: u8) {
+        self.exit_code = Some(exit_code);
+    }
+
+    /// What the program exited with, if it's stopped that way.
+    pub fn exit_code(&self) -> Option<u8> {
+        self.exit_code
+    }
+
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    // Given an effective address, gives the actual computed value of its
+    // register/s plus displacement
+    fn effective_address(&self, address: &EffectiveAddress) -> u16 {
+        let value = |register| {
+            self.cpu
+                .get_value(*WIDE_REGISTER_TABLE.get(&register).unwrap())
+        };
+        let base = match address.base {
+            Base::BxSi => value(BX).wrapping_add(value(SI)),
+            Base::BxDi => value(BX).wrapping_add(value(DI)),
+            Base::BpSi => value(BP).wrapping_add(value(SI)),
+            Base::BpDi => value(BP).wrapping_add(value(DI)),
+            Base::Si => value(SI),
+            Base::Di => value(DI),
+            Base::Bp => value(BP),
+            Base::Bx => value(BX),
+            Base::Direct => 0,
+        };
+        base.wrapping_add(address.displacement as u16)
+    }
+
+    // The value of the segment register a memory operand goes through
+    fn segment(&self, address: &EffectiveAddress, prefixes: Prefixes) -> u16 {
+        self.cpu
+            .get_value(prefixes.segment.unwrap_or(address.default_segment()))
+    }
+
+    /// Reads the byte or word at segment:offset. The high byte of a word at
+    /// offset ffff comes from the start of the same segment, not the next one.
+    pub fn read_memory(&self, segment: u16, offset: u16, width: Width) -> u16 {
+        let byte = |offset: u16| self.memory[physical_address(segment, offset)];
+        match width {
+            Width::Byte => byte(offset) as u16,
+            Width::Word => u16::from_le_bytes([byte(offset), byte(offset.wrapping_add(1))]),
+        }
+    }
+
+    /// Writes the byte or word at segment:offset, unless any of it is ROM.
+    pub fn write_memory(
+        &mut self,
+        segment: u16,
+        offset: u16,
+        width: Width,
+        value: u16,
+    ) -> Result<(), SimError> {
+        let bytes = match width {
+            Width::Byte => &value.to_le_bytes()[..1],
+            Width::Word => &value.to_le_bytes()[..],
+        };
+        let addresses = (0..).map(|i| physical_address(segment, offset.wrapping_add(i)));
+        if let Some(address) = addresses
+            .clone()
+            .take(bytes.len())
+            .find(|address| *address >= self.rom_start)
+        {
+            return Err(SimError::RomWrite { address });
+        }
+        for (address, byte) in addresses.zip(bytes) {
+            self.memory[address] = *byte;
+        }
+        Ok(())
+    }
+
+    fn read(&self, operand: &Operand, width: Width, prefixes: Prefixes) -> Result<u16, SimError> {
+        match operand {
+            Operand::Register(register) => Ok(self.cpu.get_value(*register)),
+            Operand::Memory(address) => Ok(self.read_memory(
+                self.segment(address, prefixes),
+                self.effective_address(address),
+                width,
+            )),
+            Operand::Immediate(value) => Ok(*value),
+            Operand::None | Operand::Relative(_) | Operand::Far { .. } => Ok(0),
+        }
+    }
+
+    fn write(
+        &mut self,
+        operand: &Operand,
+        width: Width,
+        prefixes: Prefixes,
+        value: u16,
+    ) -> Result<(), SimError> {
+        match operand {
+            Operand::Register(register) => {
+                self.cpu.set(*register, value);
+                Ok(())
+            }
+            Operand::Memory(address) => self.write_memory(
+                self.segment(address, prefixes),
+                self.effective_address(address),
+                width,
+                value,
+            ),
+            Operand::None | Operand::Immediate(_) | Operand::Relative(_) | Operand::Far { .. } => {
+                Ok(())
+            }
+        }
+    }
+
+    // A segment:offset pair in memory, the offset first
+    fn read_far(&self, operand: &Operand, prefixes: Prefixes) -> (u16, u16) {
+        match operand {
+            Operand::Memory(address) => {
+                let segment = self.segment(address, prefixes);
+                let offset = self.effective_address(address);
+                (
+                    self.read_memory(segment, offset.wrapping_add(2), Width::Word),
+                    self.read_memory(segment, offset, Width::Word),
+                )
+            }
+            Operand::Far { segment, offset } => (*segment, *offset),
+            _ => (0, 0),
+        }
+    }
+
+    // Pushes a word at ss:sp, growing the stack down
+    fn push(&mut self, value: u16) -> Result<(), SimError> {
+        let sp = self.cpu.get_value(Registers::_SP).wrapping_sub(2);
+        self.cpu.set(Registers::_SP, sp);
+        self.write_memory(self.cpu.get_value(Registers::_SS), sp, Width::Word, value)
+    }
+
+    fn pop(&mut self) -> u16 {
+        let sp = self.cpu.get_value(Registers::_SP);
+        let value = self.read_memory(self.cpu.get_value(Registers::_SS), sp, Width::Word);
+        self.cpu.set(Registers::_SP, sp.wrapping_add(2));
+        value
+    }
+
+    // Jumps to another segment, pushing where to come back to first if it's a
+    // call
+    fn far_jump(
+        &mut self,
+        operand: &Operand,
+        prefixes: Prefixes,
+        call: bool,
+    ) -> Result<(), SimError> {
+        let (segment, offset) = self.read_far(operand, prefixes);
+        if call {
+            self.push(self.cpu.get_value(Registers::_CS))?;
+            self.push(self.cpu.ip)?;
+        }
+        self.cpu.set(Registers::_CS, segment);
+        self.cpu.ip = offset;
+        Ok(())
+    }
+
+    // Runs a string instruction once, or for as long as its rep prefix says.
+    // The source is ds:si unless overridden, the destination always es:di.
+    fn string(&mut self, instruction: &Instruction) -> Result<(), SimError> {
+        let (opcode, width, prefixes) =
+            (instruction.opcode, instruction.width, instruction.prefixes);
+        let accumulator = match width {
+            Width::Byte => Registers::_AL,
+            Width::Word => Registers::_AX,
+        };
+        let size = match width {
+            Width::Byte => 1,
+            Width::Word => 2u16,
+        };
+        // backwards when the direction flag is set
+        let step = if self.cpu.flag(DIRECTION_FLAG) {
+            size.wrapping_neg()
+        } else {
+            size
+        };
+
+        loop {
+            if prefixes.repeat.is_some() && self.cpu.get_value(Registers::_CX) == 0 {
+                break;
+            }
+            let source = self
+                .cpu
+                .get_value(prefixes.segment.unwrap_or(Registers::_DS));
+            let destination = self.cpu.get_value(Registers::_ES);
+            let (si, di) = (
+                self.cpu.get_value(Registers::_SI),
+                self.cpu.get_value(Registers::_DI),
+            );
+            let (uses_si, uses_di) = match opcode {
+                Opcode::Movs => {
+                    let value = self.read_memory(source, si, width);
+                    self.write_memory(destination, di, width, value)?;
+                    (true, true)
+                }
+                Opcode::Cmps => {
+                    let value = self.read_memory(source, si, width);
+                    let other = self.read_memory(destination, di, width);
+                    self.cpu.sub(width, value, other);
+                    (true, true)
+                }
+                Opcode::Scas => {
+                    let value = self.read_memory(destination, di, width);
+                    self.cpu.sub(width, self.cpu.get_value(accumulator), value);
+                    (false, true)
+                }
+                Opcode::Lods => {
+                    let value = self.read_memory(source, si, width);
+                    self.cpu.set(accumulator, value);
+                    (true, false)
+                }
+                _ => {
+                    self.write_memory(destination, di, width, self.cpu.get_value(accumulator))?;
+                    (false, true)
+                }
+            };
+            if uses_si {
+                self.cpu.set(Registers::_SI, si.wrapping_add(step));
+            }
+            if uses_di {
+                self.cpu.set(Registers::_DI, di.wrapping_add(step));
+            }
+
+            let Some(repeat) = prefixes.repeat else {
+                break;
+            };
+            let count = self.cpu.get_value(Registers::_CX).wrapping_sub(1);
+            self.cpu.set(Registers::_CX, count);
+            if matches!(opcode, Opcode::Cmps | Opcode::Scas)
+                && self.cpu.flag(ZERO_FLAG) != (repeat == Repeat::Rep)
+            {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    // Pushes flags, cs and ip, then goes wherever the vector table at 0000:0000
+    // says the handler is, with interrupts and trapping off. That is unless
+    // the host handles it.
+    fn interrupt(&mut self, vector: u8) -> Result<(), SimError> {
+        // out of the map while it runs, so it can have the whole machine
+        if let Some(mut handler) = self.handlers.remove(&vector) {
+            let result = handler.interrupt(self, vector);
+            self.handlers.entry(vector).or_insert(handler);
+            return result;
+        }
+
+        self.push(self.cpu.flags | RESERVED_FLAGS)?;
+        self.cpu.flags &= !(INTERRUPT_FLAG | TRAP_FLAG);
+        self.push(self.cpu.get_value(Registers::_CS))?;
+        self.push(self.cpu.ip)?;
+
+        let entry = vector as u16 * 4;
+        self.cpu.ip = self.read_memory(0, entry, Width::Word);
+        let segment = self.read_memory(0, entry + 2, Width::Word);
+        self.cpu.set(Registers::_CS, segment);
+        Ok(())
+    }
+
+    // Whether a conditional jump or loop branches. The loops count cx down
+    // first, without touching the flags.
+    fn jump_taken(&mut self, opcode: Opcode) -> bool {
+        let flag = |flag| self.cpu.flag(flag);
+        let less = flag(SIGN_FLAG) != flag(OVERFLOW_FLAG);
+        match opcode {
+            Opcode::Jo => flag(OVERFLOW_FLAG),
+            Opcode::Jno => !flag(OVERFLOW_FLAG),
+            Opcode::Jb => flag(CARRY_FLAG),
+            Opcode::Jnb => !flag(CARRY_FLAG),
+            Opcode::Je => flag(ZERO_FLAG),
+            Opcode::Jne => !flag(ZERO_FLAG),
+            Opcode::Jbe => flag(CARRY_FLAG) || flag(ZERO_FLAG),
+            Opcode::Jnbe => !flag(CARRY_FLAG) && !flag(ZERO_FLAG),
+            Opcode::Js => flag(SIGN_FLAG),
+            Opcode::Jns => !flag(SIGN_FLAG),
+            Opcode::Jp => flag(PARITY_FLAG),
+            Opcode::Jnp => !flag(PARITY_FLAG),
+            Opcode::Jl => less,
+            Opcode::Jnl => !less,
+            Opcode::Jle => less || flag(ZERO_FLAG),
+            Opcode::Jnle => !less && !flag(ZERO_FLAG),
+            Opcode::Jcxz => self.cpu.get_value(Registers::_CX) == 0,
+            Opcode::Loop | Opcode::Loopz | Opcode::Loopnz => {
+                let zero = flag(ZERO_FLAG);
+                let count = self.cpu.get_value(Registers::_CX).wrapping_sub(1);
+                self.cpu.set(Registers::_CX, count);
+                match opcode {
+                    Opcode::Loopz => count != 0 && zero,
+                    Opcode::Loopnz => count != 0 && !zero,
+                    _ => count != 0,
+                }
+            }
+            _ => false,
+        }
+    }
+
+    // Executes a decoded instruction, moving ip on to whatever runs next
+    fn execute(&mut self, instruction: &Instruction) -> Result<(), SimError> {
+        // anything that branches does so from the end of the instruction
+        self.cpu.ip = self.cpu.ip.wrapping_add(instruction.length as u16);
+
+        let [destination, source] = &instruction.operands;
+        let (width, prefixes) = (instruction.width, instruction.prefixes);
+        let value = self.read(source, width, prefixes)?;
+
+        match instruction.opcode {
+            Opcode::Mov => self.write(destination, width, prefixes, value)?,
+            Opcode::Add
+            | Opcode::Adc
+            | Opcode::Sub
+            | Opcode::Sbb
+            | Opcode::Cmp
+            | Opcode::And
+            | Opcode::Or
+            | Opcode::Xor
+            | Opcode::Test => {
+                let current = self.read(destination, width, prefixes)?;
+                let result = match instruction.opcode {
+                    Opcode::Add => self.cpu.add(width, current, value),
+                    Opcode::Adc => self.cpu.adc(width, current, value),
+                    Opcode::Sub | Opcode::Cmp => self.cpu.sub(width, current, value),
+                    Opcode::Sbb => self.cpu.sbb(width, current, value),
+                    Opcode::And | Opcode::Test => self.cpu.logic(width, current & value),
+                    Opcode::Or => self.cpu.logic(width, current | value),
+                    _ => self.cpu.logic(width, current ^ value),
+                };
+                // cmp and test only keep the flags
+                if !matches!(instruction.opcode, Opcode::Cmp | Opcode::Test) {
+                    self.write(destination, width, prefixes, result)?;
+                }
+            }
+            Opcode::Rol
+            | Opcode::Ror
+            | Opcode::Rcl
+            | Opcode::Rcr
+            | Opcode::Shl
+            | Opcode::Shr
+            | Opcode::Sar => {
+                let current = self.read(destination, width, prefixes)?;
+                let result = self
+                    .cpu
+                    .shift(instruction.opcode, width, current, value as u8);
+                self.write(destination, width, prefixes, result)?;
+            }
+            Opcode::Mul | Opcode::Imul => {
+                let value = self.read(destination, width, prefixes)?;
+                self.cpu
+                    .mul(width, value, instruction.opcode == Opcode::Imul);
+            }
+            Opcode::Div | Opcode::Idiv => {
+                let value = self.read(destination, width, prefixes)?;
+                // the 8086 comes back to the instruction after
+                if !self
+                    .cpu
+                    .div(width, value, instruction.opcode == Opcode::Idiv)
+                {
+                    self.interrupt(DIVIDE_ERROR)?;
+                }
+            }
+            Opcode::Movs | Opcode::Cmps | Opcode::Scas | Opcode::Lods | Opcode::Stos => {
+                self.string(instruction)?
+            }
+            Opcode::Not | Opcode::Neg | Opcode::Inc | Opcode::Dec => {
+                let current = self.read(destination, width, prefixes)?;
+                let result = match instruction.opcode {
+                    Opcode::Not => !current,
+                    Opcode::Neg => self.cpu.neg(width, current),
+                    Opcode::Inc => self.cpu.inc(width, current),
+                    _ => self.cpu.dec(width, current),
+                };
+                self.write(destination, width, prefixes, result)?;
+            }
+            Opcode::Push => {
+                let mut value = self.read(destination, width, prefixes)?;
+                // the 8086 pushes sp as it is after the decrement
+                if *destination == Operand::Register(Registers::_SP) {
+                    value = value.wrapping_sub(2);
+                }
+                self.push(value)?;
+            }
+            Opcode::Pop => {
+                let value = self.pop();
+                self.write(destination, width, prefixes, value)?;
+            }
+            Opcode::Pushf => self.push(self.cpu.flags | RESERVED_FLAGS)?,
+            Opcode::Popf => self.cpu.flags = self.pop() & DEFINED_FLAGS,
+            Opcode::Jo
+            | Opcode::Jno
+            | Opcode::Jb
+            | Opcode::Jnb
+            | Opcode::Je
+            | Opcode::Jne
+            | Opcode::Jbe
+            | Opcode::Jnbe
+            | Opcode::Js
+            | Opcode::Jns
+            | Opcode::Jp
+            | Opcode::Jnp
+            | Opcode::Jl
+            | Opcode::Jnl
+            | Opcode::Jle
+            | Opcode::Jnle
+            | Opcode::Loopnz
+            | Opcode::Loopz
+            | Opcode::Loop
+            | Opcode::Jcxz => {
+                if let Operand::Relative(displacement) = destination
+                    && self.jump_taken(instruction.opcode)
+                {
+                    self.cpu.ip = self.cpu.ip.wrapping_add_signed(*displacement);
+                }
+            }
+            Opcode::Call | Opcode::Jmp => {
+                let call = instruction.opcode == Opcode::Call;
+                match destination {
+                    Operand::Relative(displacement) => {
+                        if call {
+                            self.push(self.cpu.ip)?;
+                        }
+                        self.cpu.ip = self.cpu.ip.wrapping_add_signed(*displacement);
+                    }
+                    _ => {
+                        let target = self.read(destination, width, prefixes)?;
+                        if call {
+                            self.push(self.cpu.ip)?;
+                        }
+                        self.cpu.ip = target;
+                    }
+                }
+            }
+            Opcode::CallFar => self.far_jump(destination, prefixes, true)?,
+            Opcode::JmpFar => self.far_jump(destination, prefixes, false)?,
+            Opcode::Int => {
+                let vector = self.read(destination, width, prefixes)?;
+                self.interrupt(vector as u8)?;
+            }
+            Opcode::Int3 => self.interrupt(BREAKPOINT)?,
+            Opcode::Into => {
+                if self.cpu.flag(OVERFLOW_FLAG) {
+                    self.interrupt(OVERFLOW)?;
+                }
+            }
+            Opcode::Hlt => self.halted = true,
+            Opcode::Cli => self.cpu.flags &= !INTERRUPT_FLAG,
+            Opcode::Sti => self.cpu.flags |= INTERRUPT_FLAG,
+            Opcode::Cld => self.cpu.flags &= !DIRECTION_FLAG,
+            Opcode::Std => self.cpu.flags |= DIRECTION_FLAG,
+            Opcode::Iret => {
+                self.cpu.ip = self.pop();
+                let segment = self.pop();
+                self.cpu.set(Registers::_CS, segment);
+                self.cpu.flags = self.pop() & DEFINED_FLAGS;
+            }
+            Opcode::Ret | Opcode::Retf => {
+                self.cpu.ip = self.pop();
+                if instruction.opcode == Opcode::Retf {
+                    let segment = self.pop();
+                    self.cpu.set(Registers::_CS, segment);
+                }
+                if let Operand::Immediate(bytes) = destination {
+                    let sp = self.cpu.get_value(Registers::_SP).wrapping_add(*bytes);
+                    self.cpu.set(Registers::_SP, sp);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Where the next instruction is fetched from
+    fn instruction_address(&self) -> usize {
+        physical_address(self.cpu.get_value(Registers::_CS), self.cpu.ip)
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.exit_code.is_none() && !self.halted && self.instruction_address() != self.code_end
+    }
+
+    /// Decodes and executes the instruction at cs:ip, which has to be in the
+    /// loaded program.
+    pub fn step(&mut self) -> Result<Instruction, SimError> {
+        let address = self.instruction_address();
+        if !(self.code_start..self.code_end).contains(&address) {
+            return Err(SimError::OutsideProgram { address });
+        }
+        let instruction = decode(&self.memory[..self.code_end], address)?;
+        self.execute(&instruction)?;
+        Ok(instruction)
+    }
+
+    /// Like [`step`](Self::step), but gives back the instruction's line of the
+    /// trace [`run`](Self::run) makes.
+    pub fn trace_step(&mut self) -> Result<String, SimError> {
+        let before = self.cpu.clone();
+        let instruction = self.step()?;
+        Ok(format!(
+            "{} ; {}",
+            instruction.nasm(),
+            trace::changes(&before, &self.cpu)
+        ))
+    }
+
+    /// Runs the loaded program until ip walks off the end of it, it halts or
+    /// it terminates, giving back a trace of every instruction as it was
+    /// executed in the reference sim86's format.
+    pub fn run(&mut self) -> Result<String, SimError> {
+        let mut buffer_out = String::new();
+
+        while self.is_running() {
+            buffer_out.push_str(&self.trace_step()?);
+            buffer_out.push('\n');
+        }
+
+        buffer_out.push('\n');
+        buffer_out.push_str(&trace::final_registers(&self.cpu));
+
+        Ok(buffer_out)
+    }
+}
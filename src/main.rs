@@ -1,6 +1,35 @@
-use clap::Parser;
-use sim8086::disassemble;
-use std::{fs::File, io::Read};
+use clap::{CommandFactory, Parser, ValueEnum, error::ErrorKind};
+use sim8086::{
+    Clock, Dos, Keyboard, Machine, SimError, Video, disassemble, disassemble_nasm, load_com,
+    load_exe, load_hex, trace,
+};
+use std::{
+    fs::File,
+    io,
+    io::{Read, Write},
+    path::PathBuf,
+    process,
+    str::FromStr,
+};
+
+// Where programs that get a segment of their own are put, well clear of the
+// vector table
+const LOAD_SEGMENT: u16 = 0x1000;
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Format {
+    /// Bare machine code, run from address 0 unless it's loaded elsewhere
+    Raw,
+    /// A DOS .COM program, run from 0100h after a program segment prefix
+    Com,
+    /// A DOS .EXE program, relocated to just after a program segment prefix
+    Exe,
+    /// Intel HEX records, loaded where they say
+    Hex,
+    /// A ROM image, mapped read only at the top of memory and booted from
+    /// FFFF:0000 like an 8086 coming out of reset
+    Boot,
+}
 
 #[derive(Parser)]
 #[command(version, about)]
@@ -8,7 +37,8 @@ struct Args {
     /// The path to the binary file to read in
     #[arg(short, long)]
     file: String,
-    /// Whether to execute the instructions
+    /// Whether to execute the instructions, tracing each one, rather than
+    /// disassembling them
     #[arg(
         short,
         long,
@@ -19,7 +49,50 @@ struct Args {
     )]
     exec: bool,
 
-    /// Whether to dump the memory
+    /// Whether to disassemble in NASM syntax that reassembles to the exact
+    /// same bytes
+    #[arg(
+        short,
+        long,
+        default_missing_value("true"),
+        default_value("false"),
+        num_args(0..=1),
+        require_equals(false)
+    )]
+    nasm: bool,
+
+    /// What sort of program the file holds, for executing it
+    #[arg(long, value_enum, default_value_t = Format::Raw)]
+    format: Format,
+
+    /// Where to load a raw file, as a hex SEG:OFF, starting there. Other
+    /// formats say where they go themselves
+    #[arg(long, value_parser = parse_address)]
+    load_at: Option<(u16, u16)>,
+
+    /// Where to start executing a raw file from, in decimal or 0x prefixed
+    /// hex. Other formats say where they start themselves
+    #[arg(long, value_parser = parse_u16)]
+    ip: Option<u16>,
+
+    /// The arguments a .COM or .EXE program finds in its command tail
+    #[arg(long = "args", default_value(""))]
+    arguments: String,
+
+    /// The directory DOS file calls are confined to. Without one they're all
+    /// refused
+    #[arg(long)]
+    sandbox: Option<PathBuf>,
+
+    /// The fixed date and time DOS reports, as YYYY-MM-DDTHH:MM:SS
+    #[arg(long, value_parser = parse_clock)]
+    clock: Option<Clock>,
+
+    /// Keys for the BIOS keyboard to hand out, in order
+    #[arg(long, default_value(""))]
+    keys: String,
+
+    /// Whether to dump the memory after executing
     #[arg(
         short,
         long,
@@ -31,10 +104,143 @@ struct Args {
     dump: bool,
 }
 
+fn parse_u16(value: &str) -> Result<u16, String> {
+    match value.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => value.parse(),
+    }
+    .map_err(|error| error.to_string())
+}
+
+fn parse_address(value: &str) -> Result<(u16, u16), String> {
+    let invalid = || format!("expected SEG:OFF in hex, got {value}");
+    let (segment, offset) = value.split_once(':').ok_or_else(invalid)?;
+    match (
+        u16::from_str_radix(segment, 16),
+        u16::from_str_radix(offset, 16),
+    ) {
+        (Ok(segment), Ok(offset)) => Ok((segment, offset)),
+        _ => Err(invalid()),
+    }
+}
+
+fn parse_clock(value: &str) -> Result<Clock, String> {
+    let invalid = || format!("expected YYYY-MM-DDTHH:MM:SS, got {value}");
+    let (date, time) = value.split_once('T').ok_or_else(invalid)?;
+    let fields = |part: &str| {
+        part.split(['-', ':'])
+            .map(|field| field.parse::<u16>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| invalid())
+    };
+    match (&fields(date)?[..], &fields(time)?[..]) {
+        (
+            &[year, month @ 1..=12, day @ 1..=31],
+            &[hour @ 0..24, minute @ 0..60, second @ 0..60],
+        ) => Ok(Clock::new(
+            year,
+            month as u8,
+            day as u8,
+            hour as u8,
+            minute as u8,
+            second as u8,
+        )),
+        _ => Err(invalid()),
+    }
+}
+
+// The arguments as DOS hands them over, with the space after the program's
+// name
+fn command_tail(args: &Args) -> Vec<u8> {
+    match args.arguments.as_str() {
+        "" => Vec::new(),
+        arguments => format!(" {arguments}").into_bytes(),
+    }
+}
+
+// Gives back the exit code the program terminated with, if it did
+fn run(buffer: Vec<u8>, args: &Args) -> Result<Option<u8>, SimError> {
+    if !args.exec {
+        if args.nasm {
+            println!("{}", disassemble_nasm(&buffer)?);
+        } else {
+            println!("{}", disassemble(&buffer)?);
+        }
+        return Ok(None);
+    }
+
+    let mut machine = Machine::new();
+    match args.format {
+        Format::Raw => {
+            let (segment, offset) = args.load_at.unwrap_or_default();
+            machine.load_at(segment, offset, &buffer)?;
+            if let Some(ip) = args.ip {
+                machine.cpu.ip = ip;
+            }
+        }
+        Format::Com => {
+            load_com(&mut machine, LOAD_SEGMENT, &buffer, &command_tail(args))?;
+        }
+        Format::Exe => {
+            load_exe(&mut machine, LOAD_SEGMENT, &buffer, &command_tail(args))?;
+        }
+        Format::Hex => load_hex(&mut machine, &String::from_utf8_lossy(&buffer))?,
+        Format::Boot => {
+            machine.map_rom(&buffer)?;
+            machine.reset();
+        }
+    }
+
+    // a booted ROM is the BIOS, and sets up its own vector table
+    if !matches!(args.format, Format::Boot) {
+        let mut dos = Dos::new(io::stdin().lock(), io::stdout());
+        dos.sandbox = args.sandbox.clone();
+        dos.clock = args.clock.unwrap_or_default();
+        dos.install(&mut machine);
+        Video::new().install(&mut machine);
+        let mut keyboard = Keyboard::new();
+        keyboard.type_text(&args.keys);
+        keyboard.install(&mut machine);
+    }
+
+    // the trace goes out a line at a time, so what it got through is there
+    // even if it fails. DOS programs print to stdout themselves, so theirs
+    // goes to stderr to stay clear of it
+    let mut output: Box<dyn Write> = match args.format {
+        Format::Raw | Format::Boot => Box::new(io::stdout()),
+        Format::Com | Format::Exe | Format::Hex => Box::new(io::stderr()),
+    };
+    writeln!(output, "--- {} execution ---", args.file)?;
+    while machine.is_running() {
+        writeln!(output, "{}", machine.trace_step()?)?;
+    }
+    writeln!(output, "\n{}", trace::final_registers(&machine.cpu))?;
+
+    if args.dump {
+        let _ = std::fs::write(
+            PathBuf::from_str("sim86_memory.data").unwrap(),
+            machine.memory(),
+        );
+    }
+
+    Ok(machine.exit_code())
+}
+
 fn main() {
     let args = Args::parse();
-    let is_executing = args.exec;
-    let is_dumping = args.dump;
+    for (option, given) in [
+        ("--load-at", args.load_at.is_some()),
+        ("--ip", args.ip.is_some()),
+    ] {
+        if given && !matches!(args.format, Format::Raw) {
+            Args::command()
+                .error(
+                    ErrorKind::ArgumentConflict,
+                    format!("{option} only applies to --format raw"),
+                )
+                .exit();
+        }
+    }
 
     let mut file = File::open(format!("./{}", args.file)).expect("file not found");
 
@@ -43,5 +249,12 @@ fn main() {
     // read in the file
     let _bytes = file.read_to_end(&mut buffer).expect("unable to read");
 
-    println!("{}", disassemble(buffer, is_executing, is_dumping));
+    match run(buffer, &args) {
+        Ok(Some(exit_code)) => process::exit(exit_code.into()),
+        Ok(None) => {}
+        Err(error) => {
+            eprintln!("error: {error}");
+            process::exit(1);
+        }
+    }
 }
@@ -1,5 +1,4 @@
-use once_cell::unsync::Lazy;
-use std::{cell::RefCell, collections::HashMap, fmt::Display, sync::LazyLock};
+use std::{collections::HashMap, fmt::Display, sync::LazyLock};
 
 // Registers
 const AL: u8 = 0b0000_0000;
@@ -21,6 +20,48 @@ pub const BP: u8 = 0b0000_0101;
 pub const SI: u8 = 0b0000_0110;
 pub const DI: u8 = 0b0000_0111;
 
+// Segment registers
+pub const ES: u8 = 0b0000_0000;
+pub const CS: u8 = 0b0000_0001;
+pub const SS: u8 = 0b0000_0010;
+pub const DS: u8 = 0b0000_0011;
+
+// Flags, by bit in the flags register
+pub const CARRY_FLAG: u16 = 0b0000_0000_0000_0001;
+pub const PARITY_FLAG: u16 = 0b0000_0000_0000_0100;
+pub const AUX_CARRY_FLAG: u16 = 0b0000_0000_0001_0000;
+pub const ZERO_FLAG: u16 = 0b0000_0000_0100_0000;
+pub const SIGN_FLAG: u16 = 0b0000_0000_1000_0000;
+pub const TRAP_FLAG: u16 = 0b0000_0001_0000_0000;
+pub const INTERRUPT_FLAG: u16 = 0b0000_0010_0000_0000;
+pub const DIRECTION_FLAG: u16 = 0b0000_0100_0000_0000;
+pub const OVERFLOW_FLAG: u16 = 0b0000_1000_0000_0000;
+// every bit that means something
+pub const DEFINED_FLAGS: u16 = CARRY_FLAG
+    | PARITY_FLAG
+    | AUX_CARRY_FLAG
+    | ZERO_FLAG
+    | SIGN_FLAG
+    | TRAP_FLAG
+    | INTERRUPT_FLAG
+    | DIRECTION_FLAG
+    | OVERFLOW_FLAG;
+// bits the 8086 always reads back as set: 1 and 12-15
+pub const RESERVED_FLAGS: u16 = 0b1111_0000_0000_0010;
+
+// in the order they sit in the flags register
+pub const FLAG_NAMES: [(u16, char); 9] = [
+    (CARRY_FLAG, 'C'),
+    (PARITY_FLAG, 'P'),
+    (AUX_CARRY_FLAG, 'A'),
+    (ZERO_FLAG, 'Z'),
+    (SIGN_FLAG, 'S'),
+    (TRAP_FLAG, 'T'),
+    (INTERRUPT_FLAG, 'I'),
+    (DIRECTION_FLAG, 'D'),
+    (OVERFLOW_FLAG, 'O'),
+];
+
 pub static REGISTER_TABLE: LazyLock<HashMap<u8, Registers>> = LazyLock::new(|| {
     let mut register_table = HashMap::new();
 
@@ -51,27 +92,18 @@ pub static WIDE_REGISTER_TABLE: LazyLock<HashMap<u8, Registers>> = LazyLock::new
     wide_register_table
 });
 
-#[derive(Debug)]
-pub struct Register {
-    pub value: u16,
-}
+pub static SEGMENT_REGISTER_TABLE: LazyLock<HashMap<u8, Registers>> = LazyLock::new(|| {
+    let mut segment_register_table = HashMap::new();
 
-thread_local! {
-    // actual register values
-    static _AX: Lazy<RefCell<Register>> = Lazy::new(|| RefCell::new(Register { value: 0x00 }));
-    static _BX: Lazy<RefCell<Register>> = Lazy::new(|| RefCell::new(Register { value: 0x00 }));
-    static _CX: Lazy<RefCell<Register>> = Lazy::new(|| RefCell::new(Register { value: 0x00 }));
-    static _DX: Lazy<RefCell<Register>> = Lazy::new(|| RefCell::new(Register { value: 0x00 }));
-    static _SP: Lazy<RefCell<Register>> = Lazy::new(|| RefCell::new(Register { value: 0x00 }));
-    static _BP: Lazy<RefCell<Register>> = Lazy::new(|| RefCell::new(Register { value: 0x00 }));
-    static _SI: Lazy<RefCell<Register>> = Lazy::new(|| RefCell::new(Register { value: 0x00 }));
-    static _DI: Lazy<RefCell<Register>> = Lazy::new(|| RefCell::new(Register { value: 0x00 }));
+    segment_register_table.insert(ES, Registers::_ES);
+    segment_register_table.insert(CS, Registers::_CS);
+    segment_register_table.insert(SS, Registers::_SS);
+    segment_register_table.insert(DS, Registers::_DS);
 
-    pub static ZERO_FLAG: Lazy<RefCell<bool>> = Lazy::new(|| RefCell::new(false));
-    static SIGN_FLAG: Lazy<RefCell<bool>> = Lazy::new(|| RefCell::new(false));
-}
+    segment_register_table
+});
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Registers {
     _AX,
     _BX,
@@ -89,179 +121,10 @@ pub enum Registers {
     _CH,
     _DL,
     _DH,
-}
-
-impl Registers {
-    pub fn update_wide(&self, value: u16) {
-        match self {
-            Self::_AX => _AX.with(|register| register.borrow_mut().set(value)),
-            Self::_BX => _BX.with(|register| register.borrow_mut().set(value)),
-            Self::_CX => _CX.with(|register| register.borrow_mut().set(value)),
-            Self::_DX => _DX.with(|register| register.borrow_mut().set(value)),
-            Self::_SP => _SP.with(|register| register.borrow_mut().set(value)),
-            Self::_BP => _BP.with(|register| register.borrow_mut().set(value)),
-            Self::_SI => _SI.with(|register| register.borrow_mut().set(value)),
-            Self::_DI => _DI.with(|register| register.borrow_mut().set(value)),
-            Self::_AL
-            | Self::_AH
-            | Self::_BL
-            | Self::_BH
-            | Self::_CL
-            | Self::_CH
-            | Self::_DL
-            | Self::_DH => panic!("can't set 8 bit register with a u16"),
-        }
-    }
-
-    pub fn update(&self, value: u8) {
-        match self {
-            Self::_AL => _AX.with(|register| register.borrow_mut().set_low(value)),
-            Self::_AH => _AX.with(|register| register.borrow_mut().set_high(value)),
-            Self::_BL => _BX.with(|register| register.borrow_mut().set_low(value)),
-            Self::_BH => _BX.with(|register| register.borrow_mut().set_high(value)),
-            Self::_CL => _CX.with(|register| register.borrow_mut().set_low(value)),
-            Self::_CH => _CX.with(|register| register.borrow_mut().set_high(value)),
-            Self::_DL => _DX.with(|register| register.borrow_mut().set_low(value)),
-            Self::_DH => _DX.with(|register| register.borrow_mut().set_high(value)),
-            Self::_AX
-            | Self::_BX
-            | Self::_CX
-            | Self::_DX
-            | Self::_SP
-            | Self::_BP
-            | Self::_SI
-            | Self::_DI => panic!("can't set a 16 bit register with a u8"),
-        }
-    }
-
-    pub fn add_wide(&self, value: u16) {
-        match self {
-            Self::_AX => _AX.with(|register| register.borrow_mut().add(value)),
-            Self::_BX => _BX.with(|register| register.borrow_mut().add(value)),
-            Self::_CX => _CX.with(|register| register.borrow_mut().add(value)),
-            Self::_DX => _DX.with(|register| register.borrow_mut().add(value)),
-            Self::_SP => _SP.with(|register| register.borrow_mut().add(value)),
-            Self::_BP => _BP.with(|register| register.borrow_mut().add(value)),
-            Self::_SI => _SI.with(|register| register.borrow_mut().add(value)),
-            Self::_DI => _DI.with(|register| register.borrow_mut().add(value)),
-            Self::_AL
-            | Self::_AH
-            | Self::_BL
-            | Self::_BH
-            | Self::_CL
-            | Self::_CH
-            | Self::_DL
-            | Self::_DH => todo!(),
-        }
-    }
-
-    pub fn sub_wide(&self, value: u16) {
-        match self {
-            Self::_AX => _AX.with(|register| register.borrow_mut().sub(value)),
-            Self::_BX => _BX.with(|register| register.borrow_mut().sub(value)),
-            Self::_CX => _CX.with(|register| register.borrow_mut().sub(value)),
-            Self::_DX => _DX.with(|register| register.borrow_mut().sub(value)),
-            Self::_SP => _SP.with(|register| register.borrow_mut().sub(value)),
-            Self::_BP => _BP.with(|register| register.borrow_mut().sub(value)),
-            Self::_SI => _SI.with(|register| register.borrow_mut().sub(value)),
-            Self::_DI => _DI.with(|register| register.borrow_mut().sub(value)),
-            Self::_AL
-            | Self::_AH
-            | Self::_BL
-            | Self::_BH
-            | Self::_CL
-            | Self::_CH
-            | Self::_DL
-            | Self::_DH => todo!(),
-        }
-    }
-
-    pub fn cmp(&self, value: u16) {
-        match self {
-            Self::_AX => _AX.with(|register| register.borrow_mut().cmp(value)),
-            Self::_BX => _BX.with(|register| register.borrow_mut().cmp(value)),
-            Self::_CX => _CX.with(|register| register.borrow_mut().cmp(value)),
-            Self::_DX => _DX.with(|register| register.borrow_mut().cmp(value)),
-            Self::_SP => _SP.with(|register| register.borrow_mut().cmp(value)),
-            Self::_BP => _BP.with(|register| register.borrow_mut().cmp(value)),
-            Self::_SI => _SI.with(|register| register.borrow_mut().cmp(value)),
-            Self::_DI => _DI.with(|register| register.borrow_mut().cmp(value)),
-            Self::_AL
-            | Self::_AH
-            | Self::_BL
-            | Self::_BH
-            | Self::_CL
-            | Self::_CH
-            | Self::_DL
-            | Self::_DH => todo!(),
-        }
-    }
-
-    pub fn get_value(&self) -> u16 {
-        match self {
-            Self::_DI => _DI.with(|register| register.borrow().value),
-            Self::_SI => _SI.with(|register| register.borrow().value),
-            Self::_AX => _AX.with(|register| register.borrow().value),
-            Self::_BX => _BX.with(|register| register.borrow().value),
-            Self::_CX => _CX.with(|register| register.borrow().value),
-            Self::_DX => _DX.with(|register| register.borrow().value),
-            Self::_AL => _AX.with(|register| register.borrow().value),
-            Self::_AH => _AX.with(|register| register.borrow().value),
-            Self::_BL => _BX.with(|register| register.borrow().value),
-            Self::_BH => _BX.with(|register| register.borrow().value),
-            Self::_CL => _CX.with(|register| register.borrow().value),
-            Self::_CH => _CX.with(|register| register.borrow().value),
-            Self::_DL => _DX.with(|register| register.borrow().value),
-            Self::_DH => _DX.with(|register| register.borrow().value),
-            Self::_SP => _SP.with(|register| register.borrow().value),
-            Self::_BP => _BP.with(|register| register.borrow().value),
-        }
-    }
-
-    pub fn updated_value(&self) -> String {
-        match self {
-            Self::_DI => format!("di {:#x}", _DI.with(|register| register.borrow().value)),
-            Self::_SI => format!("si {:#x}", _SI.with(|register| register.borrow().value)),
-            Self::_AX => format!("ax {:#x}", _AX.with(|register| register.borrow().value)),
-            Self::_BX => format!("bx {:#x}", _BX.with(|register| register.borrow().value)),
-            Self::_CX => format!("cx {:#x}", _CX.with(|register| register.borrow().value)),
-            Self::_DX => format!("dx {:#x}", _DX.with(|register| register.borrow().value)),
-            Self::_AL => format!("al {:#x}", _AX.with(|register| register.borrow().value)),
-            Self::_AH => format!("ah {:#x}", _AX.with(|register| register.borrow().value)),
-            Self::_BL => format!("bl {:#x}", _BX.with(|register| register.borrow().value)),
-            Self::_BH => format!("bh {:#x}", _BX.with(|register| register.borrow().value)),
-            Self::_CL => format!("cl {:#x}", _CX.with(|register| register.borrow().value)),
-            Self::_CH => format!("ch {:#x}", _CX.with(|register| register.borrow().value)),
-            Self::_DL => format!("dl {:#x}", _DX.with(|register| register.borrow().value)),
-            Self::_DH => format!("dh {:#x}", _DX.with(|register| register.borrow().value)),
-            Self::_SP => format!("sp {:#x}", _SP.with(|register| register.borrow().value)),
-            Self::_BP => format!("bp {:#x}", _BP.with(|register| register.borrow().value)),
-        }
-    }
-
-    pub fn print() {
-        println!("ax: {:#04x}", _AX.with(|register| register.borrow().value));
-        println!("bx: {:#04x}", _BX.with(|register| register.borrow().value));
-        println!("cx: {:#04x}", _CX.with(|register| register.borrow().value));
-        println!("dx: {:#04x}", _DX.with(|register| register.borrow().value));
-        println!("sp: {:#04x}", _SP.with(|register| register.borrow().value));
-        println!("bp: {:#04x}", _BP.with(|register| register.borrow().value));
-        println!("si: {:#04x}", _SI.with(|register| register.borrow().value));
-        println!("di: {:#04x}", _DI.with(|register| register.borrow().value));
-        println!("\n\n");
-        print!("Flags: ");
-        ZERO_FLAG.with(|flag| {
-            if *flag.borrow() {
-                print!("Z");
-            }
-        });
-        SIGN_FLAG.with(|flag| {
-            if *flag.borrow() {
-                print!("S");
-            }
-        });
-        print!("\n");
-    }
+    _ES,
+    _CS,
+    _SS,
+    _DS,
 }
 
 impl Display for Registers {
@@ -283,88 +146,10 @@ impl Display for Registers {
             Self::_DH => write!(f, "dh"),
             Self::_SP => write!(f, "sp"),
             Self::_BP => write!(f, "bp"),
+            Self::_ES => write!(f, "es"),
+            Self::_CS => write!(f, "cs"),
+            Self::_SS => write!(f, "ss"),
+            Self::_DS => write!(f, "ds"),
         }
     }
 }
-
-impl Register {
-    fn set(&mut self, value: u16) {
-        self.value = value;
-    }
-
-    fn add(&mut self, value: u16) {
-        self.value = self.value.wrapping_add(value);
-
-        if self.value == 0 {
-            ZERO_FLAG.with(|flag| {
-                flag.replace(true);
-            })
-        } else {
-            ZERO_FLAG.with(|flag| {
-                flag.replace(false);
-            })
-        }
-
-        if self.value & 0x8000 == 1 {
-            SIGN_FLAG.with(|flag| {
-                flag.replace(true);
-            })
-        } else {
-            SIGN_FLAG.with(|flag| {
-                flag.replace(false);
-            })
-        }
-    }
-
-    fn sub(&mut self, value: u16) {
-        self.value = self.value.wrapping_sub(value);
-
-        if self.value == 0 {
-            ZERO_FLAG.with(|flag| {
-                flag.replace(true);
-            })
-        } else {
-            ZERO_FLAG.with(|flag| {
-                flag.replace(false);
-            })
-        }
-
-        if self.value & 0x8000 == 1 {
-            SIGN_FLAG.with(|flag| {
-                flag.replace(true);
-            })
-        }
-    }
-
-    fn cmp(&mut self, value: u16) {
-        let value = self.value.wrapping_sub(value);
-
-        if value == 0 {
-            ZERO_FLAG.with(|flag| {
-                flag.replace(true);
-            })
-        } else {
-            ZERO_FLAG.with(|flag| {
-                flag.replace(false);
-            })
-        }
-
-        // If the value is negative
-        if value & 0x8000 == 1 {
-            SIGN_FLAG.with(|flag| {
-                flag.replace(true);
-            })
-        }
-    }
-
-    // little endian
-    fn set_low(&mut self, value: u8) {
-        self.value &= !0xff00;
-        self.value |= (value as u16) << 8;
-    }
-
-    fn set_high(&mut self, value: u8) {
-        self.value &= !0xff;
-        self.value |= value as u16
-    }
-}
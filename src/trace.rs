@@ -0,0 +1,61 @@
+use crate::{
+    cpu::{Cpu, flag_names},
+    tables::{
+        AX, BP, BX, CS, CX, DI, DS, DX, ES, SEGMENT_REGISTER_TABLE, SI, SP, SS, WIDE_REGISTER_TABLE,
+    },
+};
+
+// Every register the trace reports on, in the order it reports them
+fn registers(cpu: &Cpu) -> Vec<(String, u16)> {
+    let general =
+        [AX, BX, CX, DX, SP, BP, SI, DI].map(|reg| *WIDE_REGISTER_TABLE.get(&reg).unwrap());
+    let segments = [ES, CS, SS, DS].map(|reg| *SEGMENT_REGISTER_TABLE.get(&reg).unwrap());
+    general
+        .iter()
+        .chain(&segments)
+        .map(|register| (register.to_string(), cpu.get_value(*register)))
+        .collect()
+}
+
+/// Describes what executing an instruction changed, the way the reference
+/// sim86 does, e.g. `cx:0x0->0x3 ip:0x0->0x3 flags:->Z `.
+pub fn changes(before: &Cpu, after: &Cpu) -> String {
+    let mut buffer_out = String::new();
+
+    for ((name, old), (_, new)) in registers(before).iter().zip(registers(after)) {
+        if *old != new {
+            buffer_out.push_str(&format!("{name}:{old:#x}->{new:#x} "));
+        }
+    }
+    if before.ip != after.ip {
+        buffer_out.push_str(&format!("ip:{:#x}->{:#x} ", before.ip, after.ip));
+    }
+    if before.flags != after.flags {
+        buffer_out.push_str(&format!(
+            "flags:{}->{} ",
+            flag_names(before.flags),
+            flag_names(after.flags)
+        ));
+    }
+
+    buffer_out
+}
+
+/// The "Final registers" block the reference sim86 ends its trace with,
+/// skipping anything that's zero.
+pub fn final_registers(cpu: &Cpu) -> String {
+    let mut buffer_out = String::from("Final registers:\n");
+
+    let mut registers = registers(cpu);
+    registers.push(("ip".into(), cpu.ip));
+    for (name, value) in registers {
+        if value != 0 {
+            buffer_out.push_str(&format!("{name:>8}: {value:#06x} ({value})\n"));
+        }
+    }
+    if cpu.flags != 0 {
+        buffer_out.push_str(&format!("{:>8}: {}\n", "flags", flag_names(cpu.flags)));
+    }
+
+    buffer_out
+}
@@ -1,5 +1,17 @@
-use sim8086::disassemble;
-use std::{env::current_dir, fs::File, io::Read};
+use sim8086::{
+    AUX_CARRY_FLAG, Base, CARRY_FLAG, Clock, DIRECTION_FLAG, DecodeError, Dos, EffectiveAddress,
+    ExeHeader, INTERRUPT_FLAG, Keyboard, LoadError, Machine, OVERFLOW_FLAG, Opcode, Operand,
+    Registers, SimError, Video, Width, ZERO_FLAG, decode, disassemble, disassemble_nasm,
+    flag_names, load_com, load_exe, load_hex,
+};
+use std::{
+    cell::RefCell,
+    env::{current_dir, temp_dir},
+    fs::File,
+    io::{Cursor, ErrorKind, Read, Write},
+    process::{self, Command},
+    rc::Rc,
+};
 
 #[test]
 fn listing_37() {
@@ -14,7 +26,7 @@ fn listing_37() {
     let _bytes = file.read_to_end(&mut buffer).expect("unable to read");
 
     assert_eq!(
-        disassemble(buffer, false),
+        disassemble(&buffer).unwrap(),
         r#"bits 16 
 
 mov cx, bx
@@ -35,7 +47,7 @@ fn listing_38() {
     let _bytes = file.read_to_end(&mut buffer).expect("unable to read");
 
     assert_eq!(
-        disassemble(buffer, false),
+        disassemble(&buffer).unwrap(),
         r#"bits 16 
 
 mov cx, bx
@@ -66,7 +78,7 @@ fn listing_39() {
     let _bytes = file.read_to_end(&mut buffer).expect("unable to read");
 
     assert_eq!(
-        disassemble(buffer, false),
+        disassemble(&buffer).unwrap(),
         r#"bits 16 
 
 mov si, bx
@@ -102,7 +114,7 @@ fn listing_40() {
     let _bytes = file.read_to_end(&mut buffer).expect("unable to read");
 
     assert_eq!(
-        disassemble(buffer, false),
+        disassemble(&buffer).unwrap(),
         r#"bits 16 
 
 mov ax, [bx + di - 37]
@@ -133,7 +145,7 @@ fn listing_41() {
     let _bytes = file.read_to_end(&mut buffer).expect("unable to read");
 
     assert_eq!(
-        disassemble(buffer, false),
+        disassemble(&buffer).unwrap(),
         r#"bits 16 
 
 add bx, [bx + si]
@@ -235,3 +247,1609 @@ jcxz -40
 "#
     )
 }
+
+#[test]
+fn decode_memory_operand() {
+    // mov [bx + di - 37], cx
+    let instruction = decode(&[0x90, 0x89, 0x49, 0xdb], 1).unwrap();
+
+    assert_eq!(instruction.opcode, Opcode::Mov);
+    assert_eq!(instruction.width, Width::Word);
+    assert_eq!(instruction.length, 3);
+    assert_eq!(
+        instruction.operands[0],
+        Operand::Memory(EffectiveAddress {
+            base: Base::BxDi,
+            displacement: -37,
+            displacement_width: Some(Width::Byte),
+        })
+    );
+    assert_eq!(instruction.to_string(), "mov [bx + di - 37], cx");
+}
+
+#[test]
+fn decode_truncated_instruction() {
+    assert_eq!(
+        decode(&[0xb9, 0x03], 0),
+        Err(DecodeError::TruncatedInstruction { offset: 0 })
+    );
+}
+
+#[test]
+fn machines_do_not_share_state() {
+    let mut first = Machine::new();
+    // mov cx, 3
+    first.load(&[0xb9, 0x03, 0x00]).unwrap();
+    let mut second = Machine::new();
+    // mov cx, 7
+    second.load(&[0xb9, 0x07, 0x00]).unwrap();
+
+    first.run().unwrap();
+    second.run().unwrap();
+
+    assert_eq!(first.cpu.get_value(Registers::_CX), 3);
+    assert_eq!(second.cpu.get_value(Registers::_CX), 7);
+    assert_eq!(Machine::new().cpu.get_value(Registers::_CX), 0);
+}
+
+#[test]
+fn errors_instead_of_panics() {
+    // mov cx, 3 then half of mov bx, 1000
+    assert_eq!(
+        disassemble(&[0xb9, 0x03, 0x00, 0xbb, 0xe8]),
+        Err(SimError::TruncatedInstruction { offset: 3 })
+    );
+    // mov cx, 3 then cmc
+    assert_eq!(
+        disassemble(&[0xb9, 0x03, 0x00, 0xf5]),
+        Err(SimError::UnknownOpcode {
+            byte: 0xf5,
+            offset: 3
+        })
+    );
+}
+
+fn run_listing(name: &str) -> Machine {
+    let mut file =
+        File::open(format!("{}/{name}", current_dir().unwrap().display())).expect("file not found");
+
+    let mut buffer = Vec::new();
+
+    let _bytes = file.read_to_end(&mut buffer).expect("unable to read");
+
+    run_program(&buffer)
+}
+
+fn run_program(program: &[u8]) -> Machine {
+    let mut machine = Machine::new();
+    machine.load(program).unwrap();
+    machine.run().unwrap();
+    machine
+}
+
+// each one's bytes and how they disassemble
+fn assert_decodes(instructions: &[(&[u8], &str)]) {
+    for (bytes, text) in instructions {
+        assert_eq!(decode(bytes, 0).unwrap().to_string(), *text);
+    }
+}
+
+#[test]
+fn listing_46_flags() {
+    let machine = run_listing("listing_0046_add_sub_cmp");
+
+    assert_eq!(machine.cpu.get_value(Registers::_BX), 0xe102);
+    assert_eq!(machine.cpu.get_value(Registers::_CX), 0x0f01);
+    assert_eq!(machine.cpu.get_value(Registers::_BP), 0);
+    assert_eq!(flag_names(machine.cpu.flags), "PZ");
+}
+
+#[test]
+fn listing_48_flags() {
+    let machine = run_listing("listing_0048_ip_register");
+
+    assert_eq!(machine.cpu.get_value(Registers::_CX), 0xfce0);
+    assert_eq!(flag_names(machine.cpu.flags), "CS");
+}
+
+#[test]
+fn byte_arithmetic_flags() {
+    let mut machine = Machine::new();
+    // mov al, 127
+    // add al, 1
+    machine.load(&[0xb0, 0x7f, 0x04, 0x01]).unwrap();
+    machine.run().unwrap();
+
+    assert_eq!(machine.cpu.get_value(Registers::_AX), 0x80);
+    assert!(machine.cpu.flag(OVERFLOW_FLAG));
+    assert!(machine.cpu.flag(AUX_CARRY_FLAG));
+    assert!(!machine.cpu.flag(CARRY_FLAG));
+    assert_eq!(flag_names(machine.cpu.flags), "ASO");
+
+    // mov al, 0
+    // sub al, 1
+    machine.load(&[0xb0, 0x00, 0x2c, 0x01]).unwrap();
+    machine.run().unwrap();
+
+    assert_eq!(machine.cpu.get_value(Registers::_AX), 0xff);
+    assert_eq!(flag_names(machine.cpu.flags), "CPAS");
+}
+
+#[test]
+fn loop_counts_cx_down() {
+    let mut machine = Machine::new();
+    // mov cx, 3
+    // mov bx, 0
+    // add bx, 2
+    // loop $-3
+    machine
+        .load(&[
+            0xb9, 0x03, 0x00, 0xbb, 0x00, 0x00, 0x83, 0xc3, 0x02, 0xe2, 0xfb,
+        ])
+        .unwrap();
+    machine.run().unwrap();
+
+    assert_eq!(machine.cpu.get_value(Registers::_BX), 6);
+    assert_eq!(machine.cpu.get_value(Registers::_CX), 0);
+}
+
+#[test]
+fn signed_and_unsigned_jumps() {
+    let mut machine = Machine::new();
+    // mov ax, -1
+    // cmp ax, 1
+    // jl $+5
+    // mov bx, 1
+    // jb $+5
+    // mov dx, 3
+    machine
+        .load(&[
+            0xb8, 0xff, 0xff, 0x3d, 0x01, 0x00, 0x7c, 0x03, 0xbb, 0x01, 0x00, 0x72, 0x03, 0xba,
+            0x03, 0x00,
+        ])
+        .unwrap();
+    machine.run().unwrap();
+
+    assert_eq!(machine.cpu.get_value(Registers::_BX), 0);
+    assert_eq!(machine.cpu.get_value(Registers::_DX), 3);
+}
+
+#[test]
+fn disassembly_does_not_follow_jumps() {
+    let mut file = File::open(format!(
+        "{}/listing_0049_conditional_jumps",
+        current_dir().unwrap().display()
+    ))
+    .expect("file not found");
+
+    let mut buffer = Vec::new();
+
+    let _bytes = file.read_to_end(&mut buffer).expect("unable to read");
+
+    assert_eq!(
+        disassemble(&buffer).unwrap(),
+        r#"bits 16 
+
+mov cx, 3
+mov bx, 1000
+add bx, 10
+sub cx, 1
+jne -8
+"#
+    );
+
+    let mut machine = Machine::new();
+    machine.load(&buffer).unwrap();
+    let trace = machine.run().unwrap();
+
+    assert_eq!(trace.matches("add bx, 10").count(), 3);
+    assert_eq!(machine.cpu.get_value(Registers::_BX), 1030);
+}
+
+// What NASM assembles the disassembly back into, or nothing when it isn't
+// installed
+fn reassemble(name: &str, binary: &[u8]) -> Option<Vec<u8>> {
+    let source = temp_dir().join(format!("sim8086-{}-{name}.asm", process::id()));
+    let output = source.with_extension("bin");
+    let disassembly = disassemble_nasm(binary).unwrap();
+    std::fs::write(&source, &disassembly).unwrap();
+    let status = Command::new("nasm")
+        .args(["-f", "bin", "-o"])
+        .arg(&output)
+        .arg(&source)
+        .status();
+    let _ = std::fs::remove_file(&source);
+
+    match status {
+        Err(error) if error.kind() == ErrorKind::NotFound => {
+            eprintln!("nasm isn't installed, so {name} wasn't reassembled");
+            None
+        }
+        status => {
+            assert!(status.unwrap().success(), "{name}:\n{disassembly}");
+            let bytes = std::fs::read(&output).unwrap();
+            let _ = std::fs::remove_file(&output);
+            Some(bytes)
+        }
+    }
+}
+
+#[test]
+fn nasm_output_reassembles() {
+    for name in [
+        "listing_0037_single_register_mov",
+        "listing_0038_many_register_mov",
+        "listing_0039_more_movs",
+        "listing_0040_challenge_movs",
+        "listing_0041_add_sub_cmp_jnz",
+        "listing_0043_immediate_movs",
+        "listing_0044_register_movs",
+        "listing_0046_add_sub_cmp",
+        "listing_0048_ip_register",
+        "listing_0049_conditional_jumps",
+        "listing_0051_memory_mov",
+        "listing_0052_memory_add_loop",
+        "listing_0054_draw_rectangle",
+    ] {
+        let binary = std::fs::read(name).expect("file not found");
+        if let Some(bytes) = reassemble(name, &binary) {
+            assert_eq!(bytes, binary, "{name}");
+        }
+    }
+}
+
+#[test]
+fn nasm_output_keeps_encoding() {
+    let instructions: [(&[u8], &str); 26] = [
+        (&[0x75, 0xf8], "jne $-6"),
+        (&[0xe9, 0x00, 0x00], "jmp near $+3"),
+        (&[0xe9, 0x00, 0x01], "jmp $+259"),
+        (&[0xe9, 0xff, 0x7f], "jmp $+32770"),
+        (&[0xe9, 0xfd, 0x7f], "jmp $+32768"),
+        (&[0xeb, 0xfe], "jmp $+0"),
+        (&[0xe3, 0xfe], "jcxz $+0"),
+        (&[0x8b, 0x47, 0x00], "mov ax, [byte bx + 0]"),
+        (&[0x8b, 0x46, 0x00], "mov ax, [bp]"),
+        (&[0x89, 0x8c, 0xfd, 0xff], "mov [word si - 3], cx"),
+        (
+            &[0x83, 0x83, 0x00, 0x00, 0x07],
+            "add word [word bp + di + 0], 7",
+        ),
+        (&[0x83, 0xc3, 0x01], "add bx, 1"),
+        (&[0x81, 0xc3, 0x01, 0x00], "add bx, strict word 1"),
+        (&[0x81, 0xc3, 0xc8, 0x00], "add bx, 200"),
+        (&[0x81, 0x07, 0x80, 0xff], "add word [bx], strict word -128"),
+        (&[0x05, 0x01, 0x00], "add ax, strict word 1"),
+        (&[0x05, 0xc8, 0x00], "add ax, 200"),
+        (&[0x80, 0xc3, 0x01], "add bl, 1"),
+        (&[0x04, 0x01], "add al, 1"),
+        (&[0x80, 0xc0, 0x01], "db 0x80, 0xc0, 0x01"),
+        (&[0x81, 0xf8, 0xc8, 0x00], "db 0x81, 0xf8, 0xc8, 0x00"),
+        (&[0x26, 0x82, 0x07, 0x01], "db 0x26, 0x82, 0x07, 0x01"),
+        (&[0xc7, 0xc3, 0x01, 0x00], "db 0xc7, 0xc3, 0x01, 0x00"),
+        (&[0xc7, 0x07, 0x01, 0x00], "mov [bx], word 1"),
+        (&[0xf6, 0xc0, 0x01], "db 0xf6, 0xc0, 0x01"),
+        (&[0xf6, 0xc3, 0x01], "test bl, 1"),
+    ];
+
+    for (bytes, text) in instructions {
+        assert_eq!(decode(bytes, 0).unwrap().nasm().to_string(), text);
+    }
+
+    let binary: Vec<_> = instructions
+        .iter()
+        .flat_map(|(bytes, _)| bytes.iter().copied())
+        .collect();
+    if let Some(bytes) = reassemble("keeps_encoding", &binary) {
+        assert_eq!(bytes, binary);
+    }
+}
+
+#[test]
+fn listing_48_trace() {
+    let buffer = std::fs::read("listing_0048_ip_register").expect("file not found");
+    let mut machine = Machine::new();
+    machine.load(&buffer).unwrap();
+
+    assert_eq!(
+        machine.run().unwrap(),
+        r#"mov cx, 200 ; cx:0x0->0xc8 ip:0x0->0x3 
+mov bx, cx ; bx:0x0->0xc8 ip:0x3->0x5 
+add cx, 1000 ; cx:0xc8->0x4b0 ip:0x5->0x9 flags:->A 
+mov bx, 2000 ; bx:0xc8->0x7d0 ip:0x9->0xc 
+sub cx, bx ; cx:0x4b0->0xfce0 ip:0xc->0xe flags:A->CS 
+
+Final registers:
+      bx: 0x07d0 (2000)
+      cx: 0xfce0 (64736)
+      ip: 0x000e (14)
+   flags: CS
+"#
+    );
+}
+
+#[test]
+fn listing_49_trace() {
+    let buffer = std::fs::read("listing_0049_conditional_jumps").expect("file not found");
+    let mut machine = Machine::new();
+    machine.load(&buffer).unwrap();
+
+    assert_eq!(
+        machine.run().unwrap(),
+        r#"mov cx, 3 ; cx:0x0->0x3 ip:0x0->0x3 
+mov bx, 1000 ; bx:0x0->0x3e8 ip:0x3->0x6 
+add bx, 10 ; bx:0x3e8->0x3f2 ip:0x6->0x9 flags:->A 
+sub cx, 1 ; cx:0x3->0x2 ip:0x9->0xc flags:A-> 
+jne $-6 ; ip:0xc->0x6 
+add bx, 10 ; bx:0x3f2->0x3fc ip:0x6->0x9 flags:->P 
+sub cx, 1 ; cx:0x2->0x1 ip:0x9->0xc flags:P-> 
+jne $-6 ; ip:0xc->0x6 
+add bx, 10 ; bx:0x3fc->0x406 ip:0x6->0x9 flags:->PA 
+sub cx, 1 ; cx:0x1->0x0 ip:0x9->0xc flags:PA->PZ 
+jne $-6 ; ip:0xc->0xe 
+
+Final registers:
+      bx: 0x0406 (1030)
+      ip: 0x000e (14)
+   flags: PZ
+"#
+    );
+}
+
+#[test]
+fn execution_can_start_mid_image() {
+    let buffer = std::fs::read("listing_0048_ip_register").expect("file not found");
+    let mut machine = Machine::new();
+    machine.load(&buffer).unwrap();
+    machine.cpu.ip = 9;
+
+    assert_eq!(
+        machine.run().unwrap(),
+        r#"mov bx, 2000 ; bx:0x0->0x7d0 ip:0x9->0xc 
+sub cx, bx ; cx:0x0->0xf830 ip:0xc->0xe flags:->CPS 
+
+Final registers:
+      bx: 0x07d0 (2000)
+      cx: 0xf830 (63536)
+      ip: 0x000e (14)
+   flags: CPS
+"#
+    );
+
+    // loading again starts over from the top, wherever it ran before
+    machine.load_at(0x1000, 0, &buffer).unwrap();
+    machine.run().unwrap();
+    machine.load(&buffer).unwrap();
+    assert_eq!(machine.cpu.get_value(Registers::_CS), 0);
+    assert_eq!(machine.cpu.ip, 0);
+    machine.run().unwrap();
+}
+
+#[test]
+fn decode_segment_registers() {
+    assert_decodes(&[
+        (&[0x8e, 0xd8], "mov ds, ax"),
+        (&[0x8c, 0x46, 0x02], "mov [bp + 2], es"),
+        (&[0x26, 0x8b, 0x07], "mov ax, [es:bx]"),
+        (&[0x2e, 0xa1, 0x00, 0x01], "mov ax, [cs:256]"),
+        (
+            &[0x3e, 0xc6, 0x42, 0xff, 0x05],
+            "mov [ds:bp + si - 1], byte 5",
+        ),
+    ]);
+
+    // however many prefixes there are, they all count
+    let mut bytes = vec![0x26; 300];
+    bytes.extend([0xb8, 0x01, 0x00]);
+    let instruction = decode(&bytes, 0).unwrap();
+    assert_eq!(instruction.to_string(), "es mov ax, 1");
+    assert_eq!(instruction.length, 303);
+}
+
+#[test]
+fn segmented_addressing() {
+    let mut machine = Machine::new();
+    // mov ax, 0x1000
+    // mov es, ax
+    // mov byte [es:2], 7
+    // mov ax, 0x2000
+    // mov ss, ax
+    // mov bp, 4
+    // mov word [bp], 0x1234
+    // mov ax, 0xffff
+    // mov ds, ax
+    // mov byte [0x40], 9
+    machine
+        .load(&[
+            0xb8, 0x00, 0x10, 0x8e, 0xc0, 0x26, 0xc6, 0x06, 0x02, 0x00, 0x07, 0xb8, 0x00, 0x20,
+            0x8e, 0xd0, 0xbd, 0x04, 0x00, 0xc7, 0x46, 0x00, 0x34, 0x12, 0xb8, 0xff, 0xff, 0x8e,
+            0xd8, 0xc6, 0x06, 0x40, 0x00, 0x09,
+        ])
+        .unwrap();
+    machine.run().unwrap();
+
+    assert_eq!(machine.cpu.get_value(Registers::_ES), 0x1000);
+    // es:2
+    assert_eq!(machine.memory()[0x10002], 7);
+    // bp goes through ss
+    assert_eq!(machine.memory()[0x20004..0x20006], [0x34, 0x12]);
+    // ffff:0040 wraps around past the top of the mb
+    assert_eq!(machine.memory()[0x30], 9);
+}
+
+#[test]
+fn decode_stack_operations() {
+    assert_decodes(&[
+        (&[0x51], "push cx"),
+        (&[0x5e], "pop si"),
+        (&[0x1e], "push ds"),
+        (&[0x07], "pop es"),
+        (&[0xff, 0x32], "push word [bp + si]"),
+        (&[0x8f, 0x06, 0x00, 0x02], "pop word [512]"),
+        (&[0x9c], "pushf"),
+        (&[0x9d], "popf"),
+    ]);
+}
+
+#[test]
+fn stack_goes_through_ss() {
+    let mut machine = Machine::new();
+    // mov ax, 0x1000
+    // mov ss, ax
+    // mov sp, 0x100
+    // mov cx, 0x1234
+    // push cx
+    // pop es
+    // push es
+    // pop word [0x200]
+    // push word [0x200]
+    // pop dx
+    // sub cx, cx
+    // pushf
+    // pop ax
+    // mov bx, 0x801
+    // push bx
+    // popf
+    // push sp
+    // pop di
+    machine
+        .load(&[
+            0xb8, 0x00, 0x10, 0x8e, 0xd0, 0xbc, 0x00, 0x01, 0xb9, 0x34, 0x12, 0x51, 0x07, 0x06,
+            0x8f, 0x06, 0x00, 0x02, 0xff, 0x36, 0x00, 0x02, 0x5a, 0x29, 0xc9, 0x9c, 0x58, 0xbb,
+            0x01, 0x08, 0x53, 0x9d, 0x54, 0x5f,
+        ])
+        .unwrap();
+    machine.run().unwrap();
+
+    assert_eq!(machine.cpu.get_value(Registers::_ES), 0x1234);
+    assert_eq!(machine.cpu.get_value(Registers::_DX), 0x1234);
+    assert_eq!(machine.memory()[0x200..0x202], [0x34, 0x12]);
+    // the unused flag bits push as set
+    assert_eq!(machine.cpu.get_value(Registers::_AX), 0xf046);
+    assert_eq!(machine.cpu.flags, CARRY_FLAG | OVERFLOW_FLAG);
+    // push sp pushes the decremented sp
+    assert_eq!(machine.cpu.get_value(Registers::_DI), 0xfe);
+    assert_eq!(machine.cpu.get_value(Registers::_SP), 0x100);
+    assert_eq!(machine.memory()[0x100fe..0x10100], [0xfe, 0x00]);
+}
+
+#[test]
+fn decode_calls_and_jumps() {
+    assert_decodes(&[
+        (&[0xe8, 0xfd, 0xff], "call -3"),
+        (&[0xe9, 0x00, 0x01], "jmp 256"),
+        (&[0xea, 0x10, 0x00, 0x34, 0x12], "jmp 4660:16"),
+        (&[0x9a, 0x10, 0x00, 0x34, 0x12], "call 4660:16"),
+        (&[0xff, 0xd3], "call bx"),
+        (&[0xff, 0x27], "jmp word [bx]"),
+        (&[0xff, 0x1f], "call far [bx]"),
+        (&[0xff, 0x2e, 0x00, 0x01], "jmp far [256]"),
+        (&[0xc2, 0x04, 0x00], "ret 4"),
+        (&[0xc3], "ret"),
+        (&[0xcb], "retf"),
+    ]);
+
+    // direct or through memory, a far one's the one opcode
+    for (bytes, opcode) in [
+        (&[0xea, 0x10, 0x00, 0x34, 0x12][..], Opcode::JmpFar),
+        (&[0xff, 0x2e, 0x00, 0x01], Opcode::JmpFar),
+        (&[0x9a, 0x10, 0x00, 0x34, 0x12], Opcode::CallFar),
+        (&[0xff, 0x1f], Opcode::CallFar),
+    ] {
+        assert_eq!(decode(bytes, 0).unwrap().opcode, opcode);
+    }
+
+    // there's no far pointer in a register
+    assert_eq!(
+        decode(&[0xff, 0xd8], 0),
+        Err(DecodeError::UnknownOpcode {
+            byte: 0xff,
+            offset: 0
+        })
+    );
+}
+
+#[test]
+fn calls_return_where_they_came_from() {
+    let mut machine = Machine::new();
+    // 00: mov sp, 0x100
+    // 03: mov word [0x200], 0x18
+    // 09: call 0x12
+    // 0c: call far [0x200]
+    // 10: jmp 0x1c
+    // 12: mov bx, 7
+    // 15: ret
+    // 18: mov cx, 9
+    // 1b: retf
+    // 1c: mov dx, 3
+    machine
+        .load(&[
+            0xbc, 0x00, 0x01, 0xc7, 0x06, 0x00, 0x02, 0x18, 0x00, 0xe8, 0x06, 0x00, 0xff, 0x1e,
+            0x00, 0x02, 0xeb, 0x0a, 0xbb, 0x07, 0x00, 0xc3, 0x00, 0x00, 0xb9, 0x09, 0x00, 0xcb,
+            0xba, 0x03, 0x00,
+        ])
+        .unwrap();
+    machine.run().unwrap();
+
+    assert_eq!(machine.cpu.get_value(Registers::_BX), 7);
+    assert_eq!(machine.cpu.get_value(Registers::_CX), 9);
+    assert_eq!(machine.cpu.get_value(Registers::_DX), 3);
+    assert_eq!(machine.cpu.get_value(Registers::_SP), 0x100);
+    assert_eq!(machine.cpu.ip, 0x1f);
+    // what the far call left behind, ip then cs
+    assert_eq!(machine.memory()[0xfc..0x100], [0x10, 0x00, 0x00, 0x00]);
+}
+
+#[test]
+fn decode_logical_operations() {
+    assert_decodes(&[
+        (&[0x21, 0xd8], "and ax, bx"),
+        (&[0x0a, 0x07], "or al, [bx]"),
+        (&[0x35, 0xff, 0x00], "xor ax, 255"),
+        (&[0x83, 0xe1, 0xf0], "and cx, -16"),
+        (&[0x80, 0x0f, 0x01], "or byte [bx], 1"),
+        (&[0x85, 0x1e, 0x00, 0x01], "test [256], bx"),
+        (&[0xa8, 0x80], "test al, -128"),
+        (&[0xf6, 0x46, 0x02, 0x04], "test byte [bp + 2], 4"),
+        (&[0xf7, 0xd2], "not dx"),
+    ]);
+}
+
+#[test]
+fn logical_operations_clear_carry_and_overflow() {
+    let mut machine = Machine::new();
+    // mov ax, 0xf0f0
+    // add ax, 0x8080
+    // and ax, 0x0ff0
+    machine
+        .load(&[0xb8, 0xf0, 0xf0, 0x05, 0x80, 0x80, 0x25, 0xf0, 0x0f])
+        .unwrap();
+    machine.run().unwrap();
+    assert_eq!(machine.cpu.get_value(Registers::_AX), 0x0170);
+    // the carry and overflow from the add are gone
+    assert_eq!(flag_names(machine.cpu.flags), "");
+
+    let mut machine = Machine::new();
+    // mov bx, 0x00ff
+    // xor bx, 0x80ff
+    // not bx
+    // test bl, 0xff
+    machine
+        .load(&[
+            0xbb, 0xff, 0x00, 0x81, 0xf3, 0xff, 0x80, 0xf7, 0xd3, 0xf6, 0xc3, 0xff,
+        ])
+        .unwrap();
+    machine.run().unwrap();
+    assert_eq!(machine.cpu.get_value(Registers::_BX), 0x7fff);
+    assert_eq!(flag_names(machine.cpu.flags), "PS");
+}
+
+#[test]
+fn decode_shifts_and_rotates() {
+    assert_decodes(&[
+        (&[0xd1, 0xe0], "shl ax, 1"),
+        (&[0xd2, 0xcb], "ror bl, cl"),
+        (&[0xd3, 0x3f], "sar word [bx], cl"),
+        (&[0xd0, 0x56, 0x02], "rcl byte [bp + 2], 1"),
+        (&[0xd1, 0xda], "rcr dx, 1"),
+        (&[0xd3, 0xe9], "shr cx, cl"),
+    ]);
+}
+
+#[test]
+fn shifts_and_rotates_set_flags_like_the_8086() {
+    // each leaves its result in ax
+    let programs: [(&[u8], u16, &str); 6] = [
+        // mov al, 0x81
+        // rol al, 1
+        (&[0xb0, 0x81, 0xd0, 0xc0], 0x0003, "CO"),
+        // mov ax, 1
+        // mov cl, 33
+        // shl ax, cl
+        (&[0xb8, 0x01, 0x00, 0xb1, 0x21, 0xd3, 0xe0], 0x0000, "PZ"),
+        // mov ax, 0x8000
+        // mov cl, 4
+        // sar ax, cl
+        (&[0xb8, 0x00, 0x80, 0xb1, 0x04, 0xd3, 0xf8], 0xf800, "PS"),
+        // mov al, 0x80
+        // shr al, 1
+        (&[0xb0, 0x80, 0xd0, 0xe8], 0x0040, "O"),
+        // mov ax, 0xffff
+        // add ax, 1
+        // rcr ax, 1
+        (
+            &[0xb8, 0xff, 0xff, 0x05, 0x01, 0x00, 0xd1, 0xd8],
+            0x8000,
+            "PAZO",
+        ),
+        // mov ax, 0xffff
+        // add ax, 1
+        // mov cl, 0
+        // shl ax, cl
+        (
+            &[0xb8, 0xff, 0xff, 0x05, 0x01, 0x00, 0xb1, 0x00, 0xd3, 0xe0],
+            0x0000,
+            "CPAZ",
+        ),
+    ];
+
+    for (program, ax, flags) in programs {
+        let machine = run_program(program);
+        assert_eq!(machine.cpu.get_value(Registers::_AX), ax);
+        assert_eq!(flag_names(machine.cpu.flags), flags);
+    }
+}
+
+#[test]
+fn decode_multiply_and_divide() {
+    assert_decodes(&[
+        (&[0xf6, 0xe3], "mul bl"),
+        (&[0xf7, 0x2f], "imul word [bx]"),
+        (&[0xf7, 0x76, 0x04], "div word [bp + 4]"),
+        (&[0xf6, 0xf9], "idiv cl"),
+    ]);
+}
+
+#[test]
+fn multiply_and_divide() {
+    // each leaves its result in dx:ax
+    let programs: [(&[u8], u16, u16, &str); 5] = [
+        // mov al, 200
+        // mov bl, 3
+        // mul bl
+        (&[0xb0, 0xc8, 0xb3, 0x03, 0xf6, 0xe3], 0, 600, "CO"),
+        // mov ax, -3
+        // mov bx, 4
+        // imul bx
+        (
+            &[0xb8, 0xfd, 0xff, 0xbb, 0x04, 0x00, 0xf7, 0xeb],
+            0xffff,
+            0xfff4,
+            "",
+        ),
+        // mov al, 16
+        // mov bl, -8
+        // imul bl
+        (&[0xb0, 0x10, 0xb3, 0xf8, 0xf6, 0xeb], 0, 0xff80, ""),
+        // mov ax, 1000
+        // mov bx, 7
+        // div bx
+        (
+            &[0xb8, 0xe8, 0x03, 0xbb, 0x07, 0x00, 0xf7, 0xf3],
+            6,
+            142,
+            "",
+        ),
+        // mov ax, -7
+        // mov bl, 2
+        // idiv bl
+        (&[0xb8, 0xf9, 0xff, 0xb3, 0x02, 0xf6, 0xfb], 0, 0xfffd, ""),
+    ];
+
+    for (program, dx, ax, flags) in programs {
+        let machine = run_program(program);
+        assert_eq!(machine.cpu.get_value(Registers::_DX), dx);
+        assert_eq!(machine.cpu.get_value(Registers::_AX), ax);
+        assert_eq!(flag_names(machine.cpu.flags), flags);
+    }
+}
+
+#[test]
+fn divide_errors_interrupt() {
+    // the vector table overlaps the program, so it's only filled in once
+    // that part has run
+    // 00: mov sp, 0x400
+    // 03: mov word [0], 0x19
+    // 09: mov word [2], 0
+    // 0f: (divide)
+    // 16: mov cx, 1
+    // 19: mov dx, 0xdead
+    let program = |divide: [u8; 7]| {
+        let mut program = vec![
+            0xbc, 0x00, 0x04, 0xc7, 0x06, 0x00, 0x00, 0x19, 0x00, 0xc7, 0x06, 0x02, 0x00, 0x00,
+            0x00,
+        ];
+        program.extend(divide);
+        program.extend([0xb9, 0x01, 0x00, 0xba, 0xad, 0xde]);
+        program
+    };
+    let divides = [
+        // mov ax, 100
+        // mov bl, 0
+        // div bl
+        [0xb8, 0x64, 0x00, 0xb3, 0x00, 0xf6, 0xf3],
+        // mov ax, -128
+        // mov bl, 1
+        // idiv bl
+        [0xb8, 0x80, 0xff, 0xb3, 0x01, 0xf6, 0xfb],
+    ];
+
+    for divide in divides {
+        let machine = run_program(&program(divide));
+
+        assert_eq!(machine.cpu.get_value(Registers::_CX), 0);
+        assert_eq!(machine.cpu.get_value(Registers::_DX), 0xdead);
+        assert_eq!(machine.cpu.get_value(Registers::_SP), 0x3fa);
+        // ip, cs and flags
+        assert_eq!(
+            machine.memory()[0x3fa..0x400],
+            [0x16, 0x00, 0x00, 0x00, 0x02, 0xf0]
+        );
+    }
+}
+
+#[test]
+fn decode_inc_dec_neg_and_carries() {
+    assert_decodes(&[
+        (&[0x41], "inc cx"),
+        (&[0x4f], "dec di"),
+        (&[0xfe, 0x04], "inc byte [si]"),
+        (&[0xff, 0x0f], "dec word [bx]"),
+        (&[0xf7, 0xd8], "neg ax"),
+        (&[0x11, 0xd8], "adc ax, bx"),
+        (&[0x14, 0x05], "adc al, 5"),
+        (&[0x80, 0x1f, 0x01], "sbb byte [bx], 1"),
+    ]);
+}
+
+#[test]
+fn carries_chain_and_inc_dec_keep_them() {
+    // each leaves its result in dx:ax
+    let programs: [(&[u8], u16, u16, &str); 5] = [
+        // mov ax, 0xffff
+        // mov dx, 1
+        // add ax, 1
+        // adc dx, 0
+        (
+            &[
+                0xb8, 0xff, 0xff, 0xba, 0x01, 0x00, 0x05, 0x01, 0x00, 0x83, 0xd2, 0x00,
+            ],
+            2,
+            0,
+            "",
+        ),
+        // mov ax, 0
+        // mov dx, 2
+        // sub ax, 1
+        // sbb dx, 0
+        (
+            &[
+                0xb8, 0x00, 0x00, 0xba, 0x02, 0x00, 0x2d, 0x01, 0x00, 0x83, 0xda, 0x00,
+            ],
+            1,
+            0xffff,
+            "",
+        ),
+        // mov ax, 0xffff
+        // add ax, 1
+        // inc ax
+        (&[0xb8, 0xff, 0xff, 0x05, 0x01, 0x00, 0x40], 0, 1, "C"),
+        // mov al, 0x80
+        // dec al
+        (&[0xb0, 0x80, 0xfe, 0xc8], 0, 0x7f, "AO"),
+        // mov al, 0x80
+        // neg al
+        (&[0xb0, 0x80, 0xf6, 0xd8], 0, 0x80, "CSO"),
+    ];
+
+    for (program, dx, ax, flags) in programs {
+        let machine = run_program(program);
+        assert_eq!(machine.cpu.get_value(Registers::_DX), dx);
+        assert_eq!(machine.cpu.get_value(Registers::_AX), ax);
+        assert_eq!(flag_names(machine.cpu.flags), flags);
+    }
+}
+
+#[test]
+fn decode_string_instructions() {
+    assert_decodes(&[
+        (&[0xa4], "movsb"),
+        (&[0xab], "stosw"),
+        (&[0xad], "lodsw"),
+        (&[0xf3, 0xa4], "rep movsb"),
+        (&[0xf3, 0xa7], "repe cmpsw"),
+        (&[0xf2, 0xae], "repne scasb"),
+        (&[0x2e, 0xac], "cs lodsb"),
+        (&[0xf3, 0x26, 0xa5], "rep es movsw"),
+        (&[0xfc], "cld"),
+        (&[0xfd], "std"),
+    ]);
+}
+
+#[test]
+fn string_instructions_repeat() {
+    let mut machine = Machine::new();
+    // mov word [0x100], 0x2211
+    // mov word [0x102], 0x4433
+    // mov ax, 0x1000
+    // mov es, ax
+    // mov si, 0x100
+    // mov di, 0x10
+    // mov cx, 4
+    // rep movsb
+    // mov di, 0x10
+    // mov al, 0x33
+    // mov cx, 10
+    // repne scasb
+    machine
+        .load(&[
+            0xc7, 0x06, 0x00, 0x01, 0x11, 0x22, 0xc7, 0x06, 0x02, 0x01, 0x33, 0x44, 0xb8, 0x00,
+            0x10, 0x8e, 0xc0, 0xbe, 0x00, 0x01, 0xbf, 0x10, 0x00, 0xb9, 0x04, 0x00, 0xf3, 0xa4,
+            0xbf, 0x10, 0x00, 0xb0, 0x33, 0xb9, 0x0a, 0x00, 0xf2, 0xae,
+        ])
+        .unwrap();
+    machine.run().unwrap();
+
+    // copied from ds:si to es:di
+    assert_eq!(machine.memory()[0x10010..0x10014], [0x11, 0x22, 0x33, 0x44]);
+    assert_eq!(machine.cpu.get_value(Registers::_SI), 0x104);
+    // and found again, stopping just past it
+    assert_eq!(machine.cpu.get_value(Registers::_DI), 0x13);
+    assert_eq!(machine.cpu.get_value(Registers::_CX), 7);
+    assert!(machine.cpu.flag(ZERO_FLAG));
+
+    let mut machine = Machine::new();
+    // std
+    // mov di, 0x206
+    // mov cx, 3
+    // mov ax, 0xabcd
+    // rep stosw
+    // sti
+    machine
+        .load(&[
+            0xfd, 0xbf, 0x06, 0x02, 0xb9, 0x03, 0x00, 0xb8, 0xcd, 0xab, 0xf3, 0xab, 0xfb,
+        ])
+        .unwrap();
+    machine.run().unwrap();
+
+    // the direction flag runs it backwards
+    assert_eq!(
+        machine.memory()[0x200..0x208],
+        [0x00, 0x00, 0xcd, 0xab, 0xcd, 0xab, 0xcd, 0xab]
+    );
+    assert_eq!(machine.cpu.get_value(Registers::_DI), 0x200);
+    assert_eq!(machine.cpu.get_value(Registers::_CX), 0);
+    assert_eq!(machine.cpu.flags, DIRECTION_FLAG | INTERRUPT_FLAG);
+
+    // cld, cli
+    machine.load(&[0xfc, 0xfa]).unwrap();
+    machine.run().unwrap();
+    assert_eq!(machine.cpu.flags, 0);
+}
+
+#[test]
+fn decode_interrupts() {
+    assert_decodes(&[
+        (&[0xcd, 0x21], "int 33"),
+        (&[0xcd, 0x80], "int 128"),
+        (&[0xcc], "int3"),
+        (&[0xce], "into"),
+        (&[0xcf], "iret"),
+        (&[0xfa], "cli"),
+        (&[0xfb], "sti"),
+    ]);
+}
+
+#[test]
+fn interrupts_go_through_the_vector_table() {
+    let mut machine = Machine::new();
+    // 00: mov sp, 0x400
+    // 03: mov word [0x84], 0x15
+    // 09: mov ax, 0x200
+    // 0c: push ax
+    // 0d: popf
+    // 0e: int 0x21
+    // 10: mov bx, 2
+    // 13: jmp 0x19
+    // 15: mov cx, 1
+    // 18: iret
+    machine
+        .load(&[
+            0xbc, 0x00, 0x04, 0xc7, 0x06, 0x84, 0x00, 0x15, 0x00, 0xb8, 0x00, 0x02, 0x50, 0x9d,
+            0xcd, 0x21, 0xbb, 0x02, 0x00, 0xeb, 0x04, 0xb9, 0x01, 0x00, 0xcf,
+        ])
+        .unwrap();
+    let trace = machine.run().unwrap();
+
+    assert_eq!(machine.cpu.get_value(Registers::_CX), 1);
+    assert_eq!(machine.cpu.get_value(Registers::_BX), 2);
+    assert_eq!(machine.cpu.get_value(Registers::_SP), 0x400);
+    // interrupts are off in the handler, and back on after
+    assert!(trace.contains("int 33 ; sp:0x400->0x3fa ip:0xe->0x15 flags:I-> \n"));
+    assert!(trace.contains("iret ; sp:0x3fa->0x400 ip:0x18->0x10 flags:->I \n"));
+    assert_eq!(flag_names(machine.cpu.flags), "I");
+}
+
+#[test]
+fn host_handles_registered_interrupts() {
+    let mut machine = Machine::new();
+    machine.set_interrupt_handler(0x21, |machine: &mut Machine, vector: u8| {
+        let ax = machine.cpu.get_value(Registers::_AX);
+        machine.cpu.set(Registers::_AX, ax + 1);
+        machine.write_memory(0, 0x300, Width::Byte, vector as u16)
+    });
+    machine.set_interrupt_handler(0, |machine: &mut Machine, _| {
+        machine.cpu.set(Registers::_DX, 0xdead);
+        Ok(())
+    });
+    // mov sp, 0x400
+    // mov ah, 2
+    // int 0x21
+    // mov bx, ax
+    // mov cl, 0
+    // div cl
+    // mov cx, 5
+    machine
+        .load(&[
+            0xbc, 0x00, 0x04, 0xb4, 0x02, 0xcd, 0x21, 0x89, 0xc3, 0xb1, 0x00, 0xf6, 0xf1, 0xb9,
+            0x05, 0x00,
+        ])
+        .unwrap();
+    machine.run().unwrap();
+
+    assert_eq!(machine.cpu.get_value(Registers::_BX), 0x0201);
+    assert_eq!(machine.memory()[0x300], 0x21);
+    assert_eq!(machine.cpu.get_value(Registers::_DX), 0xdead);
+    assert_eq!(machine.cpu.get_value(Registers::_CX), 5);
+    // nothing went on the stack
+    assert_eq!(machine.cpu.get_value(Registers::_SP), 0x400);
+}
+
+// Output a test can still read once the machine has it
+#[derive(Clone, Default)]
+struct SharedOutput(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedOutput {
+    fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().write(bytes)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+fn dos_machine(program: &[u8], input: &str) -> (Machine, SharedOutput) {
+    let output = SharedOutput::default();
+    let mut machine = Machine::new();
+    Dos::new(Cursor::new(input.to_string()), output.clone()).install(&mut machine);
+    machine.load(program).unwrap();
+    (machine, output)
+}
+
+#[test]
+fn dos_console_output_and_exit() {
+    // 00: mov ah, 9
+    // 02: mov dx, 0x14
+    // 05: int 0x21
+    // 07: mov dl, '!'
+    // 09: mov ah, 2
+    // 0b: int 0x21
+    // 0d: mov ax, 0x4c03
+    // 10: int 0x21
+    // 12: mov cl, 7
+    // 14: db "Hi$"
+    let (mut machine, output) = dos_machine(
+        &[
+            0xb4, 0x09, 0xba, 0x14, 0x00, 0xcd, 0x21, 0xb2, 0x21, 0xb4, 0x02, 0xcd, 0x21, 0xb8,
+            0x03, 0x4c, 0xcd, 0x21, 0xb1, 0x07, 0x48, 0x69, 0x24,
+        ],
+        "",
+    );
+    machine.run().unwrap();
+
+    assert_eq!(output.0.borrow().as_slice(), b"Hi!");
+    assert_eq!(machine.exit_code(), Some(3));
+    // nothing runs after the exit
+    assert_eq!(machine.cpu.get_value(Registers::_CL), 0);
+}
+
+#[test]
+fn dos_console_input() {
+    // 00: mov ah, 1
+    // 02: int 0x21
+    // 04: mov bl, al
+    // 06: mov ah, 0x0a
+    // 08: mov dx, 0x20
+    // 0b: int 0x21
+    // 0d: mov ax, 0x4c00
+    // 10: int 0x21
+    // 20: db 5
+    let mut program = vec![
+        0xb4, 0x01, 0xcd, 0x21, 0x88, 0xc3, 0xb4, 0x0a, 0xba, 0x20, 0x00, 0xcd, 0x21, 0xb8, 0x00,
+        0x4c, 0xcd, 0x21,
+    ];
+    program.resize(0x20, 0);
+    program.push(5);
+    let (mut machine, output) = dos_machine(&program, "abcdefg\nxyz");
+    machine.run().unwrap();
+
+    assert_eq!(machine.cpu.get_value(Registers::_BL), b'a' as u16);
+    assert_eq!(output.0.borrow().as_slice(), b"a");
+    // as much of the rest of the line as fits, then a carriage return
+    assert_eq!(machine.memory()[0x20..0x27], *b"\x05\x04bcde\r");
+    assert_eq!(machine.exit_code(), Some(0));
+}
+
+#[test]
+fn dos_date_and_time_are_fixed() {
+    // mov ah, 0x2a
+    // int 0x21
+    // mov si, cx
+    // mov di, dx
+    // mov bl, al
+    // mov ah, 0x2c
+    // int 0x21
+    let mut machine = Machine::new();
+    let mut dos = Dos::new(Cursor::new(String::new()), std::io::sink());
+    dos.clock = Clock::new(2024, 2, 29, 13, 45, 30);
+    dos.install(&mut machine);
+    machine
+        .load(&[
+            0xb4, 0x2a, 0xcd, 0x21, 0x89, 0xce, 0x89, 0xd7, 0x88, 0xc3, 0xb4, 0x2c, 0xcd, 0x21,
+        ])
+        .unwrap();
+    machine.run().unwrap();
+
+    assert_eq!(machine.cpu.get_value(Registers::_SI), 2024);
+    assert_eq!(machine.cpu.get_value(Registers::_DI), 0x021d);
+    // a thursday
+    assert_eq!(machine.cpu.get_value(Registers::_BL), 4);
+    assert_eq!(machine.cpu.get_value(Registers::_CX), 0x0d2d);
+    assert_eq!(machine.cpu.get_value(Registers::_DX), 0x1e00);
+
+    // mov ah, 0x2a
+    // int 0x21
+    for (clock, weekday) in [
+        // a saturday, in the year before 1AD
+        (Clock::new(0, 1, 1, 0, 0, 0), 6),
+        (Clock::new(65535, 12, 31, 0, 0, 0), 2),
+    ] {
+        let mut machine = Machine::new();
+        let mut dos = Dos::new(Cursor::new(String::new()), std::io::sink());
+        dos.clock = clock;
+        dos.install(&mut machine);
+        machine.load(&[0xb4, 0x2a, 0xcd, 0x21]).unwrap();
+        machine.run().unwrap();
+        assert_eq!(machine.cpu.get_value(Registers::_AL), weekday);
+    }
+}
+
+#[test]
+fn dos_files_stay_in_the_sandbox() {
+    let sandbox = std::env::temp_dir().join(format!("sim8086-dos-{}", std::process::id()));
+    std::fs::create_dir_all(&sandbox).unwrap();
+    std::fs::write(sandbox.join("data.txt"), "hello").unwrap();
+
+    // 00: mov ah, 0x3d
+    // 02: mov al, 0
+    // 04: mov dx, 0x40
+    // 07: int 0x21
+    // 09: mov bx, ax
+    // 0b: mov ah, 0x3f
+    // 0d: mov cx, 16
+    // 10: mov dx, 0x60
+    // 13: int 0x21
+    // 15: mov si, ax
+    // 17: mov ah, 0x3e
+    // 19: int 0x21
+    // 1b: mov ah, 0x3c
+    // 1d: xor cx, cx
+    // 1f: mov dx, 0x70
+    // 22: int 0x21
+    // 24: mov di, ax
+    // 26: mov ah, 0x3c
+    // 28: mov dx, 0x49
+    // 2b: int 0x21
+    // 2d: mov ax, 0x4c00
+    // 30: int 0x21
+    // 40: db "data.txt", 0
+    // 49: db "new.txt", 0
+    // 70: db "../escape", 0
+    let mut program = vec![
+        0xb4, 0x3d, 0xb0, 0x00, 0xba, 0x40, 0x00, 0xcd, 0x21, 0x89, 0xc3, 0xb4, 0x3f, 0xb9, 0x10,
+        0x00, 0xba, 0x60, 0x00, 0xcd, 0x21, 0x89, 0xc6, 0xb4, 0x3e, 0xcd, 0x21, 0xb4, 0x3c, 0x31,
+        0xc9, 0xba, 0x70, 0x00, 0xcd, 0x21, 0x89, 0xc7, 0xb4, 0x3c, 0xba, 0x49, 0x00, 0xcd, 0x21,
+        0xb8, 0x00, 0x4c, 0xcd, 0x21,
+    ];
+    program.resize(0x40, 0);
+    program.extend(b"data.txt\0new.txt\0");
+    program.resize(0x70, 0);
+    program.extend(b"../escape\0");
+
+    let mut machine = Machine::new();
+    let mut dos = Dos::new(Cursor::new(String::new()), std::io::sink());
+    dos.sandbox = Some(sandbox.clone());
+    dos.install(&mut machine);
+    machine.load(&program).unwrap();
+    machine.run().unwrap();
+
+    // read through the first handle
+    assert_eq!(machine.cpu.get_value(Registers::_BX), 5);
+    assert_eq!(machine.cpu.get_value(Registers::_SI), 5);
+    assert_eq!(machine.memory()[0x60..0x65], *b"hello");
+    // path not found
+    assert_eq!(machine.cpu.get_value(Registers::_DI), 3);
+    assert!(!sandbox.parent().unwrap().join("escape").exists());
+    assert!(sandbox.join("new.txt").exists());
+    assert!(!machine.cpu.flag(CARRY_FLAG));
+    assert_eq!(machine.exit_code(), Some(0));
+
+    std::fs::remove_dir_all(sandbox).unwrap();
+}
+
+#[test]
+fn bios_text_mode_teletype() {
+    // 00: mov ax, 3
+    // 03: int 0x10
+    // 05: mov si, 0x20
+    // 08: mov cx, 5
+    // 0b: mov ah, 0xe
+    // 0d: lodsb
+    // 0e: int 0x10
+    // 10: loop 0xd
+    // 12: mov ah, 2
+    // 14: mov dx, 0x205
+    // 17: int 0x10
+    // 19: mov ax, 0xe5a
+    // 1c: int 0x10
+    // 1e: jmp 0x25
+    let mut program = vec![
+        0xb8, 0x03, 0x00, 0xcd, 0x10, 0xbe, 0x20, 0x00, 0xb9, 0x05, 0x00, 0xb4, 0x0e, 0xac, 0xcd,
+        0x10, 0xe2, 0xfb, 0xb4, 0x02, 0xba, 0x05, 0x02, 0xcd, 0x10, 0xb8, 0x5a, 0x0e, 0xcd, 0x10,
+        0xeb, 0x05,
+    ];
+    program.extend(b"Hi\r\nX");
+
+    let mut machine = Machine::new();
+    Video::new().install(&mut machine);
+    machine.load(&program).unwrap();
+    machine.run().unwrap();
+
+    let screen = &machine.memory()[0xb8000..0xb8000 + 80 * 25 * 2];
+    assert_eq!(screen[..4], *b"H\x07i\x07");
+    // the next line, then row 2 column 5
+    assert_eq!(screen[160..162], *b"X\x07");
+    assert_eq!(screen[330..332], *b"Z\x07");
+    assert_eq!(screen[4..6], *b" \x07");
+}
+
+#[test]
+fn bios_text_mode_scrolls() {
+    // 00: mov ax, 3
+    // 03: int 0x10
+    // 05: mov ax, 0xe0a
+    // 08: int 0x10
+    // 0a: mov al, 'A'
+    // 0c: int 0x10
+    // 0e: mov cx, 24
+    // 11: mov al, 0xa
+    // 13: int 0x10
+    // 15: loop 0x13
+    let program = [
+        0xb8, 0x03, 0x00, 0xcd, 0x10, 0xb8, 0x0a, 0x0e, 0xcd, 0x10, 0xb0, 0x41, 0xcd, 0x10, 0xb9,
+        0x18, 0x00, 0xb0, 0x0a, 0xcd, 0x10, 0xe2, 0xfc,
+    ];
+
+    let mut machine = Machine::new();
+    Video::new().install(&mut machine);
+    machine.load(&program).unwrap();
+    machine.run().unwrap();
+
+    // the 'A' on the second line went up to the first
+    assert_eq!(machine.memory()[0xb8000..0xb8002], *b"A\x07");
+    assert_eq!(machine.memory()[0xb80a0..0xb80a2], *b" \x07");
+    assert_eq!(
+        machine.memory()[0xb8000 + 24 * 160..0xb8002 + 24 * 160],
+        *b" \x07"
+    );
+}
+
+#[test]
+fn bios_graphics_mode_pixels() {
+    // mov ax, 0x13
+    // int 0x10
+    // mov ax, 0xc04
+    // mov cx, 10
+    // mov dx, 20
+    // int 0x10
+    // mov cx, 320
+    // int 0x10
+    let program = [
+        0xb8, 0x13, 0x00, 0xcd, 0x10, 0xb8, 0x04, 0x0c, 0xb9, 0x0a, 0x00, 0xba, 0x14, 0x00, 0xcd,
+        0x10, 0xb9, 0x40, 0x01, 0xcd, 0x10,
+    ];
+
+    let mut machine = Machine::new();
+    Video::new().install(&mut machine);
+    machine.load(&program).unwrap();
+    machine.run().unwrap();
+
+    let screen = &machine.memory()[0xa0000..0xa0000 + 320 * 200];
+    assert_eq!(screen[20 * 320 + 10], 4);
+    // off the right hand edge, so nowhere
+    assert_eq!(screen.iter().filter(|pixel| **pixel != 0).count(), 1);
+}
+
+#[test]
+fn bios_keyboard_reads_scripted_keys() {
+    // 00: mov ah, 1
+    // 02: int 0x16
+    // 04: mov bx, ax
+    // 06: pushf
+    // 07: pop si
+    // 08: mov ah, 0
+    // 0a: int 0x16
+    // 0c: mov cx, ax
+    // 0e: mov ah, 0
+    // 10: int 0x16
+    // 12: mov dx, ax
+    // 14: mov ah, 1
+    // 16: int 0x16
+    let program = [
+        0xb4, 0x01, 0xcd, 0x16, 0x89, 0xc3, 0x9c, 0x5e, 0xb4, 0x00, 0xcd, 0x16, 0x89, 0xc1, 0xb4,
+        0x00, 0xcd, 0x16, 0x89, 0xc2, 0xb4, 0x01, 0xcd, 0x16,
+    ];
+
+    let mut machine = Machine::new();
+    let mut keyboard = Keyboard::new();
+    keyboard.type_text("ab");
+    keyboard.install(&mut machine);
+    machine.load(&program).unwrap();
+    machine.run().unwrap();
+
+    // looking doesn't take the key
+    assert_eq!(machine.cpu.get_value(Registers::_BX), 0x1e61);
+    assert_eq!(machine.cpu.get_value(Registers::_SI) & ZERO_FLAG, 0);
+    assert_eq!(machine.cpu.get_value(Registers::_CX), 0x1e61);
+    assert_eq!(machine.cpu.get_value(Registers::_DX), 0x3062);
+    assert!(machine.cpu.flag(ZERO_FLAG));
+
+    // waiting once they've run out would never end
+    let mut machine = Machine::new();
+    Keyboard::new().install(&mut machine);
+    machine.load(&[0xb4, 0x00, 0xcd, 0x16]).unwrap();
+    assert!(matches!(machine.run(), Err(SimError::Io { .. })));
+}
+
+#[test]
+fn com_programs_start_after_their_psp() {
+    // 100: mov bl, [0x80]
+    // 104: mov si, [2]
+    // 108: mov ax, [0x82]
+    // 10b: ret
+    let program = [
+        0x8a, 0x1e, 0x80, 0x00, 0x8b, 0x36, 0x02, 0x00, 0xa1, 0x82, 0x00, 0xc3,
+    ];
+
+    let mut machine = Machine::new();
+    Dos::new(Cursor::new(String::new()), std::io::sink()).install(&mut machine);
+    load_com(&mut machine, 0x2000, &program, b" hi").unwrap();
+    assert_eq!(machine.cpu.ip, 0x100);
+    assert_eq!(machine.cpu.get_value(Registers::_SP), 0xfffe);
+    machine.run().unwrap();
+
+    for segment in [
+        Registers::_CS,
+        Registers::_DS,
+        Registers::_ES,
+        Registers::_SS,
+    ] {
+        assert_eq!(machine.cpu.get_value(segment), 0x2000);
+    }
+    // the tail's length, then the tail, then a carriage return
+    assert_eq!(machine.cpu.get_value(Registers::_BX), 3);
+    assert_eq!(machine.cpu.get_value(Registers::_AX), 0x6968);
+    assert_eq!(machine.memory()[0x20080..0x20085], *b"\x03 hi\r");
+    assert_eq!(machine.cpu.get_value(Registers::_SI), 0xa000);
+    // ret went to the int 20h at the start of the prefix
+    assert_eq!(machine.exit_code(), Some(0));
+    assert_eq!(machine.cpu.ip, 2);
+
+    // right up to the stack, and no further
+    let mut machine = Machine::new();
+    assert_eq!(load_com(&mut machine, 0x2000, &[0x90; 0xfefe], b""), Ok(()));
+    assert_eq!(
+        load_com(&mut machine, 0x2000, &[0x90; 0xfeff], b""),
+        Err(SimError::Load {
+            error: LoadError::TooLarge { size: 0xfeff }
+        })
+    );
+}
+
+// An .EXE with a two paragraph header and the relocations in it
+fn exe(image: &[u8], relocations: &[(u16, u16)], entry: (u16, u16), stack: (u16, u16)) -> Vec<u8> {
+    let size = 0x20 + image.len();
+    let mut file = Vec::new();
+    for word in [
+        0x5a4d,
+        (size % 512) as u16,
+        size.div_ceil(512) as u16,
+        relocations.len() as u16,
+        2,
+        0,
+        0xffff,
+        stack.0,
+        stack.1,
+        0,
+        entry.1,
+        entry.0,
+        0x1c,
+        0,
+    ] {
+        file.extend(u16::to_le_bytes(word));
+    }
+    for (segment, offset) in relocations {
+        file.extend(offset.to_le_bytes());
+        file.extend(segment.to_le_bytes());
+    }
+    file.resize(0x20, 0);
+    file.extend(image);
+    file
+}
+
+#[test]
+fn exe_programs_are_relocated() {
+    // the data's paragraph, then
+    // 10: mov ax, seg data
+    // 13: mov ds, ax
+    // 15: mov bx, [0]
+    // 19: hlt
+    let mut image = vec![0x34, 0x12];
+    image.resize(0x10, 0);
+    image.extend([0xb8, 0x00, 0x00, 0x8e, 0xd8, 0x8b, 0x1e, 0x00, 0x00, 0xf4]);
+    let file = exe(&image, &[(1, 1)], (1, 0), (2, 0x80));
+
+    let header = ExeHeader::parse(&file).unwrap();
+    assert_eq!(header.file_size(), file.len());
+    assert_eq!(header.header_size(), 0x20);
+
+    let mut machine = Machine::new();
+    load_exe(&mut machine, 0x2000, &file, b"").unwrap();
+    assert_eq!(machine.cpu.get_value(Registers::_SS), 0x2012);
+    assert_eq!(machine.cpu.get_value(Registers::_SP), 0x80);
+    machine.run().unwrap();
+
+    // the image starts a paragraph after the prefix
+    assert_eq!(machine.cpu.get_value(Registers::_CS), 0x2011);
+    assert_eq!(machine.cpu.get_value(Registers::_AX), 0x2010);
+    assert_eq!(machine.cpu.get_value(Registers::_DS), 0x2010);
+    assert_eq!(machine.cpu.get_value(Registers::_BX), 0x1234);
+    assert_eq!(machine.cpu.get_value(Registers::_ES), 0x2000);
+    assert_eq!(machine.memory()[0x20000..0x20002], [0xcd, 0x20]);
+}
+
+#[test]
+fn malformed_exe_headers() {
+    let image = [0x90; 0x10];
+    let file = exe(&image, &[], (0, 0), (0, 0));
+
+    let mut signature = file.clone();
+    signature[0] = b'P';
+    let mut last_page = file.clone();
+    last_page[2..4].copy_from_slice(&600u16.to_le_bytes());
+    let mut pages = file.clone();
+    pages[4..6].copy_from_slice(&2u16.to_le_bytes());
+    let mut table = file.clone();
+    table[6..8].copy_from_slice(&20u16.to_le_bytes());
+
+    for (file, error) in [
+        (&file[..10], LoadError::TruncatedHeader { length: 10 }),
+        (&signature, LoadError::BadSignature { signature: 0x5a50 }),
+        (
+            &last_page,
+            LoadError::BadImageSize {
+                pages: 1,
+                last_page: 600,
+            },
+        ),
+        (
+            &pages,
+            LoadError::TruncatedImage {
+                expected: 0x230,
+                length: 0x30,
+            },
+        ),
+        (
+            &table,
+            LoadError::BadRelocationTable {
+                offset: 0x1c,
+                count: 20,
+            },
+        ),
+    ] {
+        assert_eq!(ExeHeader::parse(file), Err(error));
+    }
+
+    // a relocation past the end of the image
+    let file = exe(&image, &[(1, 0)], (0, 0), (0, 0));
+    assert_eq!(
+        load_exe(&mut Machine::new(), 0x2000, &file, b""),
+        Err(SimError::Load {
+            error: LoadError::BadRelocation {
+                segment: 1,
+                offset: 0
+            }
+        })
+    );
+
+    // an image landing on rom
+    let file = exe(&image, &[], (0, 0), (0, 0));
+    let mut machine = Machine::new();
+    machine.map_rom(&[0; 0x20]).unwrap();
+    assert_eq!(
+        load_exe(&mut machine, 0xffee, &file, b""),
+        Err(SimError::RomWrite { address: 0xfffe0 })
+    );
+}
+
+#[test]
+fn intel_hex_loads_where_it_says() {
+    // 1000:0100: mov ax, 0x1234
+    // 1000:0103: mov bx, ax
+    let hex = "\
+:020040000001BD
+:020000021000EC
+:05010000B8341289C3B0
+:0400000310000100E8
+:00000001FF
+:0100000001FE
+";
+
+    let mut machine = Machine::new();
+    load_hex(&mut machine, hex).unwrap();
+    assert_eq!(machine.cpu.get_value(Registers::_CS), 0x1000);
+    assert_eq!(machine.cpu.ip, 0x100);
+    machine.run().unwrap();
+
+    assert_eq!(machine.cpu.get_value(Registers::_BX), 0x1234);
+    assert_eq!(machine.memory()[0x40..0x42], [0x00, 0x01]);
+    // nothing's read past the end of file record
+    assert_eq!(machine.memory()[0], 0);
+
+    // with no start address, from the lowest record, as it was addressed
+    let mut machine = Machine::new();
+    load_hex(
+        &mut machine,
+        ":020000021000EC\n:05010000B8341289C3B0\n:00000001FF\n",
+    )
+    .unwrap();
+    assert_eq!(machine.cpu.get_value(Registers::_CS), 0x1000);
+    assert_eq!(machine.cpu.ip, 0x100);
+    machine.run().unwrap();
+    assert_eq!(machine.cpu.get_value(Registers::_BX), 0x1234);
+
+    for (hex, error) in [
+        ("020040000001BD", LoadError::BadHexRecord { line: 1 }),
+        (":0200400000BD", LoadError::BadHexRecord { line: 1 }),
+        ("\n:zz", LoadError::BadHexRecord { line: 2 }),
+        (":020040000001BE", LoadError::BadHexChecksum { line: 1 }),
+        (
+            ":020000040001F9",
+            LoadError::UnsupportedHexRecord { line: 1, kind: 4 },
+        ),
+        (":00000001FF", LoadError::NoHexData),
+    ] {
+        assert_eq!(
+            load_hex(&mut Machine::new(), hex),
+            Err(SimError::Load { error })
+        );
+    }
+}
+
+#[test]
+fn raw_programs_load_anywhere() {
+    // mov ax, cs
+    let mut machine = Machine::new();
+    machine.load_at(0xf000, 0x10, &[0x8c, 0xc8]).unwrap();
+    machine.run().unwrap();
+
+    assert_eq!(machine.memory()[0xf0010..0xf0012], [0x8c, 0xc8]);
+    assert_eq!(machine.cpu.get_value(Registers::_AX), 0xf000);
+    assert_eq!(machine.cpu.ip, 0x12);
+
+    // jmp far 0x1000:0, which isn't anywhere in the program
+    let mut machine = Machine::new();
+    machine.load(&[0xea, 0x00, 0x00, 0x00, 0x10]).unwrap();
+    assert_eq!(
+        machine.run(),
+        Err(SimError::OutsideProgram { address: 0x10000 })
+    );
+
+    // off the top of memory, with no rom there
+    assert_eq!(
+        Machine::new().load_at(0xffff, 0, &[0; 0x20]),
+        Err(SimError::MemoryFault { address: 0x100000 })
+    );
+}
+
+#[test]
+fn boots_from_rom() {
+    // fffe0: mov ax, cs
+    // fffe2: mov [0x10], ax
+    // fffe5: hlt
+    // ffff0: jmp far 0xf000:0xffe0
+    let mut rom = vec![0x8c, 0xc8, 0xa3, 0x10, 0x00, 0xf4];
+    rom.resize(0x10, 0);
+    rom.extend([0xea, 0xe0, 0xff, 0x00, 0xf0]);
+    rom.resize(0x20, 0);
+    assert_eq!(decode(&rom, 5).unwrap().to_string(), "hlt");
+
+    let mut machine = Machine::new();
+    machine.map_rom(&rom).unwrap();
+    machine.reset();
+    assert_eq!(machine.cpu.get_value(Registers::_CS), 0xffff);
+    assert_eq!(machine.cpu.ip, 0);
+    machine.run().unwrap();
+
+    assert!(!machine.is_running());
+    assert_eq!(machine.cpu.get_value(Registers::_AX), 0xf000);
+    assert_eq!(machine.cpu.ip, 0xffe6);
+    assert_eq!(machine.memory()[0x10..0x12], [0x00, 0xf0]);
+
+    // the rom stays as it was, down to the byte that straddles it
+    assert_eq!(
+        machine.write_memory(0xffff, 0, Width::Byte, 0),
+        Err(SimError::RomWrite { address: 0xffff0 })
+    );
+    assert_eq!(
+        machine.write_memory(0xfffd, 0xf, Width::Word, 0),
+        Err(SimError::RomWrite { address: 0xfffe0 })
+    );
+    assert_eq!(machine.memory()[0xfffdf..0xfffe1], [0x00, 0x8c]);
+    assert_eq!(
+        machine.load_at(0xf000, 0xffd0, &[0; 0x20]),
+        Err(SimError::RomWrite { address: 0xfffe0 })
+    );
+    assert_eq!(machine.memory()[0xffff0], 0xea);
+}
+
+#[test]
+fn bios_cursor_parked_off_screen() {
+    // 00: mov ax, 3
+    // 03: int 0x10
+    // 05: mov ah, 2
+    // 07: mov dx, 0xff00
+    // 0a: int 0x10
+    // 0c: mov cx, 500
+    // 0f: mov ax, 0xe0a
+    // 12: int 0x10
+    // 14: loop 0x12
+    // 16: mov al, 'A'
+    // 18: int 0x10
+    let program = [
+        0xb8, 0x03, 0x00, 0xcd, 0x10, 0xb4, 0x02, 0xba, 0x00, 0xff, 0xcd, 0x10, 0xb9, 0xf4, 0x01,
+        0xb8, 0x0a, 0x0e, 0xcd, 0x10, 0xe2, 0xfc, 0xb0, 0x41, 0xcd, 0x10,
+    ];
+
+    let mut machine = Machine::new();
+    Video::new().install(&mut machine);
+    machine.load(&program).unwrap();
+    machine.run().unwrap();
+
+    // the first line feed brings it back to the bottom line
+    assert_eq!(
+        machine.memory()[0xb8000 + 24 * 160..0xb8002 + 24 * 160],
+        *b"A\x07"
+    );
+}